@@ -2,7 +2,7 @@ mod modules;
 pub mod utils;
 
 use crate::modules::{config::does_config_exist, script::run_script};
-use clap::{Args, Command, Error, FromArgMatches, Parser, Subcommand};
+use clap::{ArgGroup, Args, Command, Error, FromArgMatches, Parser, Subcommand};
 use itertools::Itertools;
 use lazy_static::lazy_static;
 use log::*;
@@ -31,25 +31,45 @@ enum Branches {
     Init(InitBranch),
     #[command(subcommand)]
     Run(RunBranch),
-    Clean,
+    /// Removes Generated Files from the Project
+    Clean(CleanBranch),
     /// Used to Build Textures
     // #[command(hide = true, hide_possible_values=true)]
     Build(BuildBranch),
     /// Used to Watch Updates To Files (For Script Use)
     #[command(hide = true, hide_possible_values = true)]
-    Watch,
+    Watch(WatchBranch),
     /// Used to Generate Ini for Mod
     // #[command(hide = true, hide_possible_values=true)]
     GenIni,
     /// Used Link Mod to 3DMigoto
     // #[command(hide = true, hide_possible_values=true)]
     Link(LinkBranch),
+    /// Validates the Project's Model Dump
+    Check,
+    /// Verifies the Buffers in Output against the Generated Ini
+    Verify,
+    /// Checks the Inis in Output for Missing, Unused and Duplicate References
+    Lint,
+    /// Works with 3DMigoto Frame Analysis Dumps
+    #[command(subcommand)]
+    Dump(DumpBranch),
+    /// Remaps the Project's Hashes after a Game Update
+    Rehash(RehashBranch),
+    /// Works with the Mods installed to 3DMigoto
+    #[command(subcommand)]
+    Mods(ModsBranch),
+    /// Works with the Global Texture Cache shared between Projects
+    #[command(subcommand)]
+    Cache(CacheBranch),
     /// Exports Finished Mods as a Zip File
     Export(ExportBranch),
     /// Compresses Entire Project Directory to Folder.
     Archive(ArchiveBranch),
     /// Exports Finished Mods as a Zip File
     Extract(ExtractBranch),
+    /// Merges Mods into one Mod, cycling between them with a Key
+    Merge(MergeBranch),
 
     /// Check for Updates and Update if Available
     Update,
@@ -134,6 +154,41 @@ struct BuildBranch {
     /// Force Rebuild
     #[arg(short, default_value_t = false)]
     force: bool,
+
+    /// Build Profile from the Config (e.g. dev, release)
+    #[arg(short, long)]
+    profile: Option<String>,
+}
+
+#[derive(Args, Debug, Clone)]
+#[command(group(ArgGroup::new("scope").required(true).multiple(true)))]
+struct CleanBranch {
+    /// Remove the Texture Cache (Textures/Cache)
+    #[arg(long, group = "scope")]
+    cache: bool,
+
+    /// Remove the Generated Textures, Buffers and Inis in Output
+    #[arg(long, group = "scope")]
+    output: bool,
+
+    /// Remove the Mod Linked to 3DMigoto
+    #[arg(long, group = "scope")]
+    linked: bool,
+
+    /// Remove Cached and Output Textures no Texture Unit Builds Anymore
+    #[arg(long, group = "scope")]
+    orphans: bool,
+
+    /// Only List what would be Removed
+    #[arg(long, default_value_t = false)]
+    dry_run: bool,
+}
+
+#[derive(Args, Debug, Clone)]
+struct WatchBranch {
+    /// Build Profile of the Rebuilds triggered by Changes
+    #[arg(short, long, default_value = "dev")]
+    profile: String,
 }
 
 #[derive(Args, Debug, Clone)]
@@ -143,11 +198,74 @@ struct LinkBranch {
     symlink: bool,
 }
 
+#[derive(Subcommand, Debug, Clone)]
+enum DumpBranch {
+    /// Imports a Model from a Frame Analysis Folder into Source/Model
+    Import(DumpImportBranch),
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum ModsBranch {
+    /// Lists Hashes Overridden by more than one Active Mod
+    Conflicts,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+enum CacheBranch {
+    /// Removes the Least Recently Used Textures until the Cache fits its Size Limit
+    Prune(CachePruneBranch),
+}
+
+#[derive(Args, Debug, Clone)]
+struct CachePruneBranch {
+    /// Size Limit in Megabytes. Defaults to `max_size` of the Global Cache in the Config
+    #[arg(long)]
+    max_size: Option<u64>,
+}
+
+#[derive(Args, Debug, Clone)]
+struct DumpImportBranch {
+    #[arg(required = true)]
+    /// Path to the FrameAnalysis Folder
+    frame_analysis: PathBuf,
+
+    /// IB Hash of the Model to Import. Prompts with the Dumped IBs if not given
+    #[arg(long)]
+    ib: Option<String>,
+
+    /// Name of the Component
+    #[arg(short, long, default_value = "")]
+    component: String,
+
+    /// Texture Names for ps-t0, ps-t1, ...
+    #[arg(short, long, value_delimiter = ',', default_value = "Diffuse,LightMap,MetalMap,ShadowRamp")]
+    textures: Vec<String>,
+}
+
+#[derive(Args, Debug, Clone)]
+struct RehashBranch {
+    /// Path or URL to a Yaml Mapping of Old to New Hashes
+    #[arg(short, long, required = true)]
+    map: String,
+}
+
 #[derive(Args, Debug, Clone)]
 struct ArchiveBranch {
     #[arg(default_value = "..")]
     /// Path to Where you want the project archive to be stored
     archive_path: PathBuf,
+
+    /// Archive Textures/Cache even if .gbtignore leaves it out
+    #[arg(long, default_value_t = false)]
+    include_cache: bool,
+
+    /// Archive Output even if .gbtignore leaves it out
+    #[arg(long, default_value_t = false)]
+    include_output: bool,
+
+    /// Deflate Compression Level (0-9). Defaults to the Config
+    #[arg(short, long, value_parser = clap::value_parser!(i32).range(0..=9))]
+    level: Option<i32>,
 }
 
 #[derive(Args, Debug, Clone)]
@@ -155,6 +273,33 @@ struct ExportBranch {
     #[arg(default_value = ".")]
     /// Path to Where you want the project archive to be stored
     export_path: PathBuf,
+
+    /// Deflate Compression Level (0-9). Defaults to the Config
+    #[arg(short, long, value_parser = clap::value_parser!(i32).range(0..=9))]
+    level: Option<i32>,
+}
+
+#[derive(Args, Debug, Clone)]
+struct MergeBranch {
+    #[arg(required = true)]
+    /// Projects or Mod Folders to Merge
+    paths: Vec<PathBuf>,
+
+    /// Key cycling between the Merged Mods
+    #[arg(short, long, required = true)]
+    key: String,
+
+    /// Name of the Merged Mod
+    #[arg(short, long, default_value = "Merged")]
+    name: String,
+
+    /// Path to Where you want the Merged Mod to be written
+    #[arg(short, long, default_value = "./Merged")]
+    output: PathBuf,
+
+    /// Also Export the Merged Mod as a Zip File to this Path
+    #[arg(long)]
+    export: Option<PathBuf>,
 }
 
 #[derive(Args, Debug, Clone)]
@@ -204,16 +349,46 @@ fn main() {
     match cli.main_command {
         Branches::Init(init) => scaffold::scaffold(init.project_path),
         Branches::Run(script) => run_script(script.script, cli.debug),
-        Branches::Clean => trace!("In Clean"),
-        Branches::Build(build) => build!(build.force),
-        Branches::Watch => watch(),
+        Branches::Clean(clean) => clean::clean(
+            clean.cache,
+            clean.output,
+            clean.linked,
+            clean.orphans,
+            clean.dry_run,
+        ),
+        Branches::Build(build) => build!(build.force, build.profile.as_deref()),
+        Branches::Watch(watch_branch) => watch(&watch_branch.profile),
         Branches::GenIni => gen_ini::gen_ini(),
         Branches::Link(link) => linker::link(link.symlink),
+        Branches::Check => check::check(),
+        Branches::Verify => verify::verify(),
+        Branches::Lint => lint::lint(),
+        Branches::Dump(DumpBranch::Import(import)) => dump::import(
+            &import.frame_analysis,
+            import.ib,
+            &import.component,
+            &import.textures,
+        ),
+        Branches::Mods(ModsBranch::Conflicts) => mods::conflicts(),
+        Branches::Cache(CacheBranch::Prune(prune)) => cache::prune(prune.max_size),
+        Branches::Rehash(rehash) => rehash::rehash(&rehash.map),
         Branches::Update => updater::update(),
-        Branches::Archive(archive) => archive_project(&archive.archive_path),
+        Branches::Archive(archive) => archive_project(
+            &archive.archive_path,
+            archive.level,
+            archive.include_cache,
+            archive.include_output,
+        ),
         Branches::Extract(extract) => {
             run_unzip(&extract.path_to_project_zip, &extract.extract_path)
         }
-        Branches::Export(export) => export_mod(&export.export_path),
+        Branches::Export(export) => export_mod(&export.export_path, export.level),
+        Branches::Merge(merge) => merge::merge(
+            &merge.paths,
+            &merge.name,
+            &merge.key,
+            &merge.output,
+            merge.export.as_ref(),
+        ),
     }
 }
@@ -1,9 +1,10 @@
-use anyhow::{Error, Result};
+use anyhow::{Context, Error, Result};
+use indicatif::{ProgressBar, ProgressState, ProgressStyle};
 use log::{error, info, trace};
 use normpath::PathExt;
 use std::{
     fs::{self, File},
-    io::{copy, Read, Write},
+    io::{copy, BufWriter},
     path::{Path, PathBuf},
     process::exit,
 };
@@ -14,45 +15,98 @@ use zip::{
     CompressionMethod,
 };
 
-use crate::CONFIG;
+use crate::{utils::ignore::IgnoreRules, CONFIG};
 
-use super::config::does_config_exist;
+use super::{build::build, config::does_config_exist, manifest::shipped};
 
-fn zip_dir(source: &PathBuf, target: &Path) -> Result<()> {
+/// Formats that are already compressed, and only grow when deflated again
+const STORED: [&str; 8] = ["png", "jpg", "jpeg", "webp", "zip", "7z", "rar", "gz"];
+
+fn zip_dir(source: &PathBuf, target: &Path, level: Option<i32>, ignore: &IgnoreRules) -> Result<()> {
     if !source.is_dir() {
         return Err(Error::msg("Source is not a Dir"));
     }
-    let it = WalkDir::new(source).into_iter().filter_map(|e| e.ok());
-    let file = File::create(target).unwrap();
+    // The archive may be written inside the folder it archives
+    let target_path = target
+        .parent()
+        .and_then(|f| f.normalize().ok())
+        .zip(target.file_name())
+        .map(|(parent, name)| parent.as_path().join(name));
+    let it = WalkDir::new(source)
+        .into_iter()
+        .filter_entry(|e| {
+            let relative = e.path().strip_prefix(source).unwrap();
+            relative.as_os_str().is_empty() || !ignore.is_ignored(relative, e.file_type().is_dir())
+        })
+        .filter_map(|e| e.ok())
+        .map(|e| e.into_path())
+        .filter(|e| Some(e) != target_path.as_ref());
+    zip_paths(source, it, target, level)
+}
+
+/// Zips `paths`, naming them relative to `source`. Files are streamed into the
+/// archive, so they never have to fit in memory
+fn zip_paths(
+    source: &Path,
+    paths: impl Iterator<Item = PathBuf>,
+    target: &Path,
+    level: Option<i32>,
+) -> Result<()> {
+    let paths = paths.collect::<Vec<_>>();
+    let total = paths
+        .iter()
+        .filter_map(|f| f.metadata().ok())
+        .filter(|f| f.is_file())
+        .map(|f| f.len())
+        .sum();
+    let file = File::create(target).with_context(|| format!("Failed to Create {:}", target.display()))?;
 
-    let mut zip = ZipWriter::new(file);
-    let options = FileOptions::default()
+    let mut zip = ZipWriter::new(BufWriter::new(file));
+    let deflated = FileOptions::default()
         .compression_method(CompressionMethod::Deflated)
+        .compression_level(level)
         .unix_permissions(0o755);
+    let stored = FileOptions::default()
+        .compression_method(CompressionMethod::Stored)
+        .unix_permissions(0o755);
+
+    let pb = ProgressBar::new(total);
+    let sty = ProgressStyle::with_template(
+        "{spinner:.green} [{elapsed_precise}] {wide_bar:.cyan/blue} {bytes}/{total_bytes} {msg} {eta}",
+    )
+    .unwrap()
+    .with_key("eta", |state: &ProgressState, w: &mut dyn std::fmt::Write| write!(w, "{:.1}s", state.eta().as_secs_f64()).unwrap())
+    .progress_chars("##-");
+    pb.set_style(sty);
+    pb.set_message(format!("{:}", target.file_name().unwrap_or_default().to_string_lossy()));
 
-    let mut buffer = Vec::new();
-    for entry in it {
-        let path = entry.path();
+    for path in paths {
         let name = path.strip_prefix(source).unwrap();
 
         // Write file or directory explicitly
         // Some unzip tools unzip files with directory paths correctly, some do not!
         if path.is_file() {
             trace!("adding file {path:?} as {name:?} ...");
+            let is_compressed = path.extension().is_some_and(|ext| {
+                STORED.iter().any(|f| ext.eq_ignore_ascii_case(f))
+            });
+            let options = match is_compressed {
+                true => stored,
+                false => deflated,
+            }
+            .large_file(path.metadata()?.len() >= u32::MAX as u64);
             zip.start_file(name.to_str().unwrap().to_owned(), options)?;
-            let mut f = File::open(path)?;
-
-            f.read_to_end(&mut buffer)?;
-            zip.write_all(&buffer)?;
-            buffer.clear();
+            copy(&mut pb.wrap_read(File::open(&path)?), &mut zip)
+                .with_context(|| format!("Failed to Compress {:}", path.display()))?;
         } else if !name.as_os_str().is_empty() {
             // Only if not root! Avoids path spec / warning
             // and mapname conversion failed error on unzip
             trace!("adding dir {path:?} as {name:?} ...");
-            zip.add_directory(name.to_str().unwrap().to_owned(), options)?;
+            zip.add_directory(name.to_str().unwrap().to_owned(), deflated)?;
         }
     }
     zip.finish()?;
+    pb.finish();
     Ok(())
 }
 
@@ -112,11 +166,22 @@ pub fn run_unzip(source: &PathBuf, target: &PathBuf) {
     unzip(source, target).expect("Failed to Extract")
 }
 
-pub fn run_zip(source: &PathBuf, target: &PathBuf) {
-    zip_dir(&source.normalize().unwrap().into_path_buf(), target).expect("Failed to Compress")
+/// Compression level given on the command line, or else the one in the Config
+fn compression_level(level: Option<i32>) -> Option<i32> {
+    level.or(CONFIG.lock().unwrap().compression_level)
+}
+
+pub fn run_zip(source: &Path, target: &Path, level: Option<i32>, ignore: &IgnoreRules) {
+    zip_dir(
+        &source.normalize().unwrap().into_path_buf(),
+        target,
+        compression_level(level),
+        ignore,
+    )
+    .expect("Failed to Compress")
 }
 
-pub fn export_mod(target: &PathBuf) {
+pub fn export_mod(target: &Path, level: Option<i32>) {
     if !does_config_exist() {
         error!("Config does not exist. Exiting....");
         exit(1);
@@ -126,12 +191,27 @@ pub fn export_mod(target: &PathBuf) {
         .unwrap()
         .into_path_buf()
         .join(format!("{}.zip", CONFIG.lock().unwrap().project_name));
+    // Never ship textures encoded by a faster profile. Release textures that are
+    // still current are only copied back over the ones in Output
+    build(false, Some("release"));
     info!("Exporting Mod to {:}", project_path.display());
-    run_zip(&PathBuf::from("./Output"), project_path);
+    let output = PathBuf::from("./Output");
+    zip_paths(
+        &output,
+        shipped(&output).into_iter(),
+        project_path,
+        compression_level(level),
+    )
+    .expect("Failed to Compress");
     info!("Mod Exported");
 }
 
-pub fn archive_project(target: &PathBuf) {
+pub fn archive_project(
+    target: &Path,
+    level: Option<i32>,
+    include_cache: bool,
+    include_output: bool,
+) {
     if !does_config_exist() {
         error!("Config does not exist. Exiting....");
         exit(1);
@@ -142,6 +222,45 @@ pub fn archive_project(target: &PathBuf) {
         .into_path_buf()
         .join(format!("{}.zip", CONFIG.lock().unwrap().project_name));
     info!("Compressing Project Archive to {:}", project_path.display());
-    run_zip(&PathBuf::from("."), project_path);
+    let mut ignore = IgnoreRules::load(Path::new("."));
+    if include_cache {
+        ignore.add("!Textures/Cache/");
+    }
+    if include_output {
+        ignore.add("!Output/");
+    }
+    run_zip(&PathBuf::from("."), project_path, level, &ignore);
     info!("Finished Compressing Project Archive. Exiting...");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_zip_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let source = root.join("Project");
+        fs::create_dir_all(source.join("Textures")).unwrap();
+        fs::write(source.join("Textures/Body.png"), [7; 4096]).unwrap();
+        fs::write(source.join("Config.yml"), "Project Name: Test\n".repeat(100)).unwrap();
+
+        let target = root.join("Project.zip");
+        zip_dir(&source, &target, Some(9), &IgnoreRules::default()).unwrap();
+        let mut archive = ZipArchive::new(File::open(&target).unwrap()).unwrap();
+        let png = archive.by_name("Textures/Body.png").unwrap();
+        assert_eq!(png.compression(), CompressionMethod::Stored);
+        drop(png);
+        let config = archive.by_name("Config.yml").unwrap();
+        assert_eq!(config.compression(), CompressionMethod::Deflated);
+        assert!(config.compressed_size() < config.size());
+        drop(config);
+
+        unzip(&target, &root.join("Extracted")).unwrap();
+        assert_eq!(
+            fs::read(root.join("Extracted/Textures/Body.png")).unwrap(),
+            [7; 4096]
+        );
+    }
+}
@@ -0,0 +1,779 @@
+use std::{
+    collections::HashMap,
+    fmt::{Display, Write as _},
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use thiserror::Error;
+
+/// Stride of the Position stream (POSITION, NORMAL, TANGENT) of a split VB
+pub const POSITION_STRIDE: usize = 40;
+/// Stride of the Blend stream (BLENDWEIGHTS, BLENDINDICES) of a split VB
+pub const BLEND_STRIDE: usize = 32;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum BufferError {
+    /// Failed to read or write a buffer file
+    ///
+    /// `(path, error message)`
+    #[error("{0:?}: {1}")]
+    Io(PathBuf, String),
+
+    /// Malformed line in a .fmt or text buffer dump
+    ///
+    /// `(line number, error message)`
+    #[error("Line {0}: {1}")]
+    Parse(usize, String),
+
+    /// DXGI Format that cannot be encoded or decoded
+    ///
+    /// `(format)`
+    #[error("Unsupported DXGI Format: {0}")]
+    UnsupportedFormat(String),
+
+    /// Buffer length that does not fit the stride of its layout
+    ///
+    /// `(length, stride)`
+    #[error("Buffer length {0} does not match stride {1}")]
+    Stride(usize, usize),
+
+    /// Index that no longer fits the format of its buffer once offset
+    ///
+    /// `(index, offset, format)`
+    #[error("Index {0} offset by {1} does not fit {2}")]
+    IndexOverflow(u32, u32, String),
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, BufferError> {
+    fs::read(path).map_err(|e| BufferError::Io(path.to_path_buf(), e.to_string()))
+}
+
+fn write_file(path: &Path, data: &[u8]) -> Result<(), BufferError> {
+    fs::write(path, data).map_err(|e| BufferError::Io(path.to_path_buf(), e.to_string()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormatKind {
+    Float,
+    Unorm,
+    Snorm,
+    Uint,
+    Sint,
+}
+
+/// A DXGI Format made out of 8, 16 or 32 bit components (e.g. `R32G32B32_FLOAT`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DxgiFormat {
+    name: String,
+    bits: Vec<usize>,
+    kind: FormatKind,
+}
+
+impl FromStr for DxgiFormat {
+    type Err = BufferError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let unsupported = || BufferError::UnsupportedFormat(s.to_owned());
+        let name = s.trim().trim_start_matches("DXGI_FORMAT_");
+        let (channels, kind) = name.split_once('_').ok_or_else(unsupported)?;
+        let kind = match kind {
+            "FLOAT" => FormatKind::Float,
+            "UNORM" => FormatKind::Unorm,
+            "SNORM" => FormatKind::Snorm,
+            "UINT" => FormatKind::Uint,
+            "SINT" => FormatKind::Sint,
+            _ => return Err(unsupported()),
+        };
+        let bits = channels
+            .split(|c: char| "RGBAD".contains(c))
+            .skip(1)
+            .map(|f| f.parse::<usize>().map_err(|_| unsupported()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let valid_bits = match kind {
+            FormatKind::Float => [16, 32].as_slice(),
+            _ => [8, 16, 32].as_slice(),
+        };
+        if bits.is_empty() || !bits.iter().all(|f| valid_bits.contains(f) && *f == bits[0]) {
+            return Err(unsupported());
+        }
+        Ok(Self {
+            name: name.to_owned(),
+            bits,
+            kind,
+        })
+    }
+}
+
+impl Display for DxgiFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "DXGI_FORMAT_{:}", self.name)
+    }
+}
+
+fn f16_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((half >> 10) & 0x1f) as i32;
+    let mantissa = (half & 0x3ff) as f32;
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => sign * f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+    if exponent == 0xff {
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    // Round to nearest, ties to even, dropping `shift` bits of the mantissa
+    let round = |mantissa: u32, shift: u32| {
+        let half = mantissa >> shift;
+        let rest = mantissa & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        match rest > halfway || (rest == halfway && half & 1 == 1) {
+            true => half + 1,
+            false => half,
+        }
+    };
+    if exponent <= 0 {
+        if exponent < -10 {
+            return sign;
+        }
+        return sign | round(mantissa | 0x80_0000, (14 - exponent) as u32) as u16;
+    }
+    // A carry out of the mantissa correctly bumps the exponent
+    sign | round(((exponent as u32) << 23) | mantissa, 13) as u16
+}
+
+impl DxgiFormat {
+    /// Size of a single element of this format in bytes
+    pub fn size(&self) -> usize {
+        self.bits.iter().sum::<usize>() / 8
+    }
+
+    pub fn components(&self) -> usize {
+        self.bits.len()
+    }
+
+    pub fn kind(&self) -> FormatKind {
+        self.kind
+    }
+
+    pub fn decode(&self, data: &[u8]) -> Vec<f64> {
+        let width = self.bits[0] / 8;
+        data.chunks_exact(width)
+            .take(self.components())
+            .map(|c| {
+                let (unsigned, signed) = match width {
+                    1 => (c[0] as u64, c[0] as i8 as i64),
+                    2 => {
+                        let v = u16::from_le_bytes([c[0], c[1]]);
+                        (v as u64, v as i16 as i64)
+                    }
+                    _ => {
+                        let v = u32::from_le_bytes([c[0], c[1], c[2], c[3]]);
+                        (v as u64, v as i32 as i64)
+                    }
+                };
+                let max = ((1u64 << (width * 8)) - 1) as f64;
+                match (self.kind, width) {
+                    (FormatKind::Float, 2) => f16_to_f32(unsigned as u16) as f64,
+                    (FormatKind::Float, _) => f32::from_bits(unsigned as u32) as f64,
+                    (FormatKind::Uint, _) => unsigned as f64,
+                    (FormatKind::Sint, _) => signed as f64,
+                    (FormatKind::Unorm, _) => unsigned as f64 / max,
+                    (FormatKind::Snorm, _) => (signed as f64 / (max / 2.0).floor()).max(-1.0),
+                }
+            })
+            .collect()
+    }
+
+    /// Encodes the values, zero padding any missing components
+    pub fn encode(&self, values: &[f64]) -> Vec<u8> {
+        let width = self.bits[0] / 8;
+        let max = ((1u64 << (width * 8)) - 1) as f64;
+        (0..self.components())
+            .flat_map(|i| {
+                let value = values.get(i).copied().unwrap_or_default();
+                let raw: u64 = match (self.kind, width) {
+                    (FormatKind::Float, 2) => f32_to_f16(value as f32) as u64,
+                    (FormatKind::Float, _) => (value as f32).to_bits() as u64,
+                    (FormatKind::Uint, _) => value as u64,
+                    (FormatKind::Sint, _) => value as i64 as u64,
+                    (FormatKind::Unorm, _) => (value.clamp(0.0, 1.0) * max).round() as u64,
+                    (FormatKind::Snorm, _) => {
+                        (value.clamp(-1.0, 1.0) * (max / 2.0).floor()).round() as i64 as u64
+                    }
+                };
+                raw.to_le_bytes().into_iter().take(width)
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputElement {
+    pub semantic_name: String,
+    pub semantic_index: u32,
+    pub format: DxgiFormat,
+    pub input_slot: u32,
+    pub aligned_byte_offset: usize,
+    pub input_slot_class: String,
+    pub instance_data_step_rate: u32,
+}
+
+impl InputElement {
+    /// Name used for the element in text dumps (e.g. `TEXCOORD1`)
+    pub fn name(&self) -> String {
+        match self.semantic_index {
+            0 => self.semantic_name.to_owned(),
+            index => format!("{:}{:}", self.semantic_name, index),
+        }
+    }
+
+    fn from_fields(line: usize, fields: &HashMap<String, String>) -> Result<Self, BufferError> {
+        let get = |key: &str| {
+            fields
+                .get(key)
+                .map(|f| f.as_str())
+                .ok_or_else(|| BufferError::Parse(line, format!("Element is missing {:}", key)))
+        };
+        let number = |key: &str| {
+            get(key)?
+                .parse()
+                .map_err(|_| BufferError::Parse(line, format!("Invalid {:}", key)))
+        };
+        let aligned_byte_offset = match get("AlignedByteOffset")? {
+            "append" => {
+                return Err(BufferError::Parse(
+                    line,
+                    "AlignedByteOffset=append is not supported".to_owned(),
+                ))
+            }
+            _ => number("AlignedByteOffset")? as usize,
+        };
+        Ok(Self {
+            semantic_name: get("SemanticName")?.to_owned(),
+            semantic_index: number("SemanticIndex")?,
+            format: get("Format")?.parse()?,
+            input_slot: number("InputSlot")?,
+            aligned_byte_offset,
+            input_slot_class: get("InputSlotClass")?.to_owned(),
+            instance_data_step_rate: number("InstanceDataStepRate")?,
+        })
+    }
+}
+
+/// Header and input layout shared by `.fmt` files and text buffer dumps
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fmt {
+    pub stride: usize,
+    pub topology: String,
+    /// Format of the IB
+    pub format: Option<DxgiFormat>,
+    pub elements: Vec<InputElement>,
+}
+
+struct Header {
+    fields: HashMap<String, String>,
+    elements: Vec<InputElement>,
+    /// Number of lines read before the header ended
+    consumed: usize,
+}
+
+/// Splits the `key: value` lines of a header until `stop` matches a line
+fn parse_header(s: &str, stop: impl Fn(&str) -> bool) -> Result<Header, BufferError> {
+    let mut header = HashMap::new();
+    let mut elements = vec![];
+    let mut element: Option<(usize, HashMap<String, String>)> = None;
+    let mut consumed = 0;
+    for (index, line) in s.lines().enumerate() {
+        consumed = index + 1;
+        let trimmed = line.trim();
+        if stop(trimmed) {
+            break;
+        }
+        if trimmed.is_empty() {
+            continue;
+        }
+        if trimmed.starts_with("element[") {
+            if let Some((line, fields)) = element.take() {
+                elements.push(InputElement::from_fields(line, &fields)?);
+            }
+            element = Some((index + 1, HashMap::new()));
+            continue;
+        }
+        let (key, value) = trimmed
+            .split_once(':')
+            .ok_or_else(|| BufferError::Parse(index + 1, format!("Expected `key: value`, found {:?}", trimmed)))?;
+        let (key, value) = (key.trim().to_owned(), value.trim().to_owned());
+        match element.as_mut() {
+            // Element fields are indented, header fields are not
+            Some((_, fields)) if line.starts_with(char::is_whitespace) => {
+                fields.insert(key, value);
+            }
+            _ => {
+                header.insert(key, value);
+            }
+        }
+    }
+    if let Some((line, fields)) = element.take() {
+        elements.push(InputElement::from_fields(line, &fields)?);
+    }
+    Ok(Header {
+        fields: header,
+        elements,
+        consumed,
+    })
+}
+
+impl Fmt {
+    fn from_header(header: Header) -> Result<Self, BufferError> {
+        let stride = header
+            .fields
+            .get("stride")
+            .ok_or_else(|| BufferError::Parse(0, "Missing stride".to_owned()))?
+            .parse()
+            .map_err(|_| BufferError::Parse(0, "Invalid stride".to_owned()))?;
+        let format = match header.fields.get("format") {
+            Some(format) => Some(format.parse()?),
+            None => None,
+        };
+        Ok(Self {
+            stride,
+            topology: header
+                .fields
+                .get("topology")
+                .cloned()
+                .unwrap_or("trianglelist".to_owned()),
+            format,
+            elements: header.elements,
+        })
+    }
+
+    pub fn read(path: &Path) -> Result<Self, BufferError> {
+        String::from_utf8_lossy(&read_file(path)?).parse()
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), BufferError> {
+        write_file(path, self.to_string().as_bytes())
+    }
+
+    fn elements_to_string(&self) -> String {
+        let mut out = String::new();
+        for (index, element) in self.elements.iter().enumerate() {
+            let _ = writeln!(out, "element[{:}]:", index);
+            let _ = writeln!(out, "  SemanticName: {:}", element.semantic_name);
+            let _ = writeln!(out, "  SemanticIndex: {:}", element.semantic_index);
+            let _ = writeln!(out, "  Format: {:}", element.format.name);
+            let _ = writeln!(out, "  InputSlot: {:}", element.input_slot);
+            let _ = writeln!(out, "  AlignedByteOffset: {:}", element.aligned_byte_offset);
+            let _ = writeln!(out, "  InputSlotClass: {:}", element.input_slot_class);
+            let _ = writeln!(out, "  InstanceDataStepRate: {:}", element.instance_data_step_rate);
+        }
+        out
+    }
+}
+
+impl FromStr for Fmt {
+    type Err = BufferError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_header(parse_header(s, |_| false)?)
+    }
+}
+
+impl Display for Fmt {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "stride: {:}", self.stride)?;
+        writeln!(f, "topology: {:}", self.topology)?;
+        if let Some(format) = &self.format {
+            writeln!(f, "format: {:}", format)?;
+        }
+        write!(f, "{:}", self.elements_to_string())
+    }
+}
+
+/// Decoded vertex, keyed by element name
+pub type Vertex = HashMap<String, Vec<f64>>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VertexBuffer {
+    pub stride: usize,
+    pub data: Vec<u8>,
+}
+
+impl VertexBuffer {
+    pub fn new(stride: usize, data: Vec<u8>) -> Result<Self, BufferError> {
+        if stride == 0 || !data.len().is_multiple_of(stride) {
+            return Err(BufferError::Stride(data.len(), stride));
+        }
+        Ok(Self { stride, data })
+    }
+
+    pub fn read(path: &Path, stride: usize) -> Result<Self, BufferError> {
+        Self::new(stride, read_file(path)?)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), BufferError> {
+        write_file(path, &self.data)
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.data.len() / self.stride
+    }
+
+    pub fn vertices(&self) -> impl Iterator<Item = &[u8]> {
+        self.data.chunks_exact(self.stride)
+    }
+
+    pub fn append(&mut self, other: &VertexBuffer) -> Result<(), BufferError> {
+        if self.stride != other.stride {
+            return Err(BufferError::Stride(other.data.len(), self.stride));
+        }
+        self.data.extend_from_slice(&other.data);
+        Ok(())
+    }
+
+    /// Splits the VB into the Position, Blend and Texcoord streams 3DMigoto binds separately
+    pub fn split(&self) -> Result<[VertexBuffer; 3], BufferError> {
+        let texcoord_stride = self
+            .stride
+            .checked_sub(POSITION_STRIDE + BLEND_STRIDE)
+            .filter(|f| *f > 0)
+            .ok_or(BufferError::Stride(self.data.len(), self.stride))?;
+        let mut streams = [
+            (POSITION_STRIDE, vec![]),
+            (BLEND_STRIDE, vec![]),
+            (texcoord_stride, vec![]),
+        ];
+        for vertex in self.vertices() {
+            let mut rest = vertex;
+            for (stride, data) in streams.iter_mut() {
+                let (head, tail) = rest.split_at(*stride);
+                data.extend_from_slice(head);
+                rest = tail;
+            }
+        }
+        Ok(streams.map(|(stride, data)| Self { stride, data }))
+    }
+
+    #[cfg(test)]
+    pub fn decode(&self, fmt: &Fmt) -> Vec<Vertex> {
+        self.vertices()
+            .map(|vertex| {
+                fmt.elements
+                    .iter()
+                    .filter(|f| f.aligned_byte_offset + f.format.size() <= vertex.len())
+                    .map(|f| {
+                        let start = f.aligned_byte_offset;
+                        (f.name(), f.format.decode(&vertex[start..start + f.format.size()]))
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    pub fn encode(fmt: &Fmt, vertices: &[Vertex]) -> Self {
+        let mut data = vec![0u8; fmt.stride * vertices.len()];
+        for (vertex, out) in vertices.iter().zip(data.chunks_exact_mut(fmt.stride)) {
+            for element in &fmt.elements {
+                if let Some(values) = vertex.get(&element.name()) {
+                    let start = element.aligned_byte_offset;
+                    out[start..start + element.format.size()]
+                        .copy_from_slice(&element.format.encode(values));
+                }
+            }
+        }
+        Self {
+            stride: fmt.stride,
+            data,
+        }
+    }
+
+    /// Parses a frame analysis `vb*.txt` dump into its layout and vertices
+    pub fn parse_txt(s: &str) -> Result<(Fmt, Vec<Vertex>), BufferError> {
+        let header = parse_header(s, |line| line.starts_with("vertex-data:"))?;
+        let consumed = header.consumed;
+        let fmt = Fmt::from_header(header)?;
+        let mut vertices = vec![];
+        let mut vertex = Vertex::new();
+        for (index, line) in s.lines().enumerate().skip(consumed) {
+            let line = line.trim();
+            if line.starts_with("instance-data:") {
+                break;
+            }
+            if line.is_empty() {
+                if !vertex.is_empty() {
+                    vertices.push(std::mem::take(&mut vertex));
+                }
+                continue;
+            }
+            // vb0[12]+024 NORMAL: 0.1, 0.2, 0.3
+            let parse_err = || BufferError::Parse(index + 1, format!("Invalid vertex data {:?}", line));
+            let (element, values) = line
+                .split_once(' ')
+                .and_then(|(_, rest)| rest.split_once(':'))
+                .ok_or_else(parse_err)?;
+            let values = values
+                .split(',')
+                .map(|f| f.trim().parse::<f64>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| parse_err())?;
+            vertex.insert(element.trim().to_owned(), values);
+        }
+        if !vertex.is_empty() {
+            vertices.push(vertex);
+        }
+        Ok((fmt, vertices))
+    }
+
+    /// Writes the buffer in the frame analysis `vb*.txt` layout
+    #[cfg(test)]
+    pub fn to_txt(&self, fmt: &Fmt) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "stride: {:}", self.stride);
+        let _ = writeln!(out, "first vertex: 0");
+        let _ = writeln!(out, "vertex count: {:}", self.vertex_count());
+        let _ = writeln!(out, "topology: {:}", fmt.topology);
+        out.push_str(&fmt.elements_to_string());
+        out.push_str("\nvertex-data:\n\n");
+        for (index, vertex) in self.decode(fmt).iter().enumerate() {
+            for element in &fmt.elements {
+                if let Some(values) = vertex.get(&element.name()) {
+                    let values = values.iter().map(|f| f.to_string()).collect::<Vec<_>>();
+                    let _ = writeln!(
+                        out,
+                        "vb{:}[{:}]+{:03} {:}: {:}",
+                        element.input_slot,
+                        index,
+                        element.aligned_byte_offset,
+                        element.name(),
+                        values.join(", ")
+                    );
+                }
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexBuffer {
+    pub format: DxgiFormat,
+    pub first: u32,
+    pub indices: Vec<u32>,
+}
+
+impl IndexBuffer {
+    pub fn new(format: DxgiFormat, indices: Vec<u32>) -> Result<Self, BufferError> {
+        match format.kind() == FormatKind::Uint && format.components() == 1 {
+            true => Ok(Self {
+                format,
+                first: 0,
+                indices,
+            }),
+            false => Err(BufferError::UnsupportedFormat(format.to_string())),
+        }
+    }
+
+    pub fn from_bytes(format: DxgiFormat, data: &[u8]) -> Result<Self, BufferError> {
+        let width = format.size();
+        if width == 0 || !data.len().is_multiple_of(width) {
+            return Err(BufferError::Stride(data.len(), width));
+        }
+        let indices = data
+            .chunks_exact(width)
+            .map(|f| format.decode(f)[0] as u32)
+            .collect();
+        Self::new(format, indices)
+    }
+
+    pub fn read(path: &Path, format: DxgiFormat) -> Result<Self, BufferError> {
+        Self::from_bytes(format, &read_file(path)?)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.indices
+            .iter()
+            .flat_map(|f| self.format.encode(&[*f as f64]))
+            .collect()
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), BufferError> {
+        write_file(path, &self.to_bytes())
+    }
+
+    /// Shifts every index by `offset`, used when merging parts into one VB
+    pub fn offset(&mut self, offset: u32) -> Result<(), BufferError> {
+        let max = match self.format.size() {
+            width @ 1..=3 => (1 << (width * 8)) - 1,
+            _ => u32::MAX,
+        };
+        for index in self.indices.iter_mut() {
+            *index = index
+                .checked_add(offset)
+                .filter(|f| *f <= max)
+                .ok_or_else(|| {
+                    BufferError::IndexOverflow(*index, offset, self.format.to_string())
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Re-encodes the indices using `format`
+    pub fn with_format(self, format: DxgiFormat) -> Result<Self, BufferError> {
+        let first = self.first;
+        Self::new(format, self.indices).map(|ib| Self { first, ..ib })
+    }
+
+    /// Parses a frame analysis `ib.txt` dump
+    pub fn parse_txt(s: &str) -> Result<Self, BufferError> {
+        let header = parse_header(s, |line| line.is_empty())?;
+        let consumed = header.consumed;
+        let format = header
+            .fields
+            .get("format")
+            .ok_or_else(|| BufferError::Parse(0, "Missing format".to_owned()))?
+            .parse()?;
+        let first = header
+            .fields
+            .get("first index")
+            .map(|f| f.parse().unwrap_or_default())
+            .unwrap_or_default();
+        let indices = s
+            .lines()
+            .skip(consumed)
+            .enumerate()
+            .flat_map(|(index, line)| {
+                line.split_whitespace()
+                    .map(move |f| f.parse::<u32>().map_err(|_| BufferError::Parse(consumed + index + 1, format!("Invalid index {:?}", f))))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Self::new(format, indices).map(|ib| Self { first, ..ib })
+    }
+
+    /// Writes the buffer in the frame analysis `ib.txt` layout
+    #[cfg(test)]
+    pub fn to_txt(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "byte offset: 0");
+        let _ = writeln!(out, "first index: {:}", self.first);
+        let _ = writeln!(out, "index count: {:}", self.indices.len());
+        let _ = writeln!(out, "topology: trianglelist");
+        let _ = writeln!(out, "format: {:}", self.format);
+        out.push('\n');
+        for face in self.indices.chunks(3) {
+            let face = face.iter().map(|f| f.to_string()).collect::<Vec<_>>();
+            let _ = writeln!(out, "{:}", face.join(" "));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FMT: &str = "stride: 88
+topology: trianglelist
+format: DXGI_FORMAT_R16_UINT
+element[0]:
+  SemanticName: POSITION
+  SemanticIndex: 0
+  Format: R32G32B32_FLOAT
+  InputSlot: 0
+  AlignedByteOffset: 0
+  InputSlotClass: per-vertex
+  InstanceDataStepRate: 0
+element[1]:
+  SemanticName: BLENDINDICES
+  SemanticIndex: 0
+  Format: R32G32B32A32_SINT
+  InputSlot: 0
+  AlignedByteOffset: 56
+  InputSlotClass: per-vertex
+  InstanceDataStepRate: 0
+element[2]:
+  SemanticName: TEXCOORD
+  SemanticIndex: 1
+  Format: R16G16_FLOAT
+  InputSlot: 0
+  AlignedByteOffset: 76
+  InputSlotClass: per-vertex
+  InstanceDataStepRate: 0
+";
+
+    #[test]
+    pub fn test_fmt_round_trip() {
+        let fmt = Fmt::from_str(FMT).unwrap();
+
+        assert_eq!(fmt.stride, 88);
+        assert_eq!(fmt.format.as_ref().unwrap().size(), 2);
+        assert_eq!(fmt.elements.len(), 3);
+        assert_eq!(fmt.elements[2].name(), "TEXCOORD1");
+        assert_eq!(fmt.elements[1].format.size(), 16);
+        assert_eq!(fmt.to_string(), FMT);
+        assert!(DxgiFormat::from_str("R10G10B10A2_UNORM").is_err());
+    }
+
+    #[test]
+    pub fn test_vertex_codec() {
+        let fmt = Fmt::from_str(FMT).unwrap();
+        let vertex: Vertex = [
+            ("POSITION".to_owned(), vec![1.5, -2.0, 0.25]),
+            ("BLENDINDICES".to_owned(), vec![1.0, 2.0, -3.0, 4.0]),
+            ("TEXCOORD1".to_owned(), vec![0.5, 0.75]),
+        ]
+        .into_iter()
+        .collect();
+        let vb = VertexBuffer::encode(&fmt, &[vertex.clone(), vertex.clone()]);
+
+        assert_eq!(vb.vertex_count(), 2);
+        assert_eq!(vb.decode(&fmt)[1], vertex);
+        let (parsed_fmt, vertices) = VertexBuffer::parse_txt(&vb.to_txt(&fmt)).unwrap();
+        assert_eq!(parsed_fmt.elements, fmt.elements);
+        assert_eq!(vertices[0], vertex);
+
+        let [position, blend, texcoord] = vb.split().unwrap();
+        assert_eq!(position.data.len(), 2 * POSITION_STRIDE);
+        assert_eq!(blend.data.len(), 2 * BLEND_STRIDE);
+        assert_eq!(texcoord.stride, 16);
+        assert!(VertexBuffer::new(88, vec![0; 100]).is_err());
+    }
+
+    #[test]
+    pub fn test_index_codec() {
+        let ib = IndexBuffer::parse_txt(
+            "byte offset: 0\nfirst index: 30\nindex count: 6\ntopology: trianglelist\nformat: DXGI_FORMAT_R16_UINT\n\n0 1 2\n2 1 3\n",
+        )
+        .unwrap();
+
+        assert_eq!(ib.first, 30);
+        assert_eq!(ib.to_bytes().len(), 12);
+        let mut ib = ib.with_format("DXGI_FORMAT_R32_UINT".parse().unwrap()).unwrap();
+        ib.offset(10).unwrap();
+        assert_eq!(ib.to_bytes().len(), 24);
+        assert_eq!(IndexBuffer::parse_txt(&ib.to_txt()).unwrap(), ib);
+        assert!(ib.clone().offset(u32::MAX).is_err());
+        let mut ib = ib.with_format("DXGI_FORMAT_R16_UINT".parse().unwrap()).unwrap();
+        assert!(ib.offset(65535).is_err());
+    }
+
+    #[test]
+    pub fn test_half_floats() {
+        for value in [0.0f32, 1.0, -2.5, 0.333_251_95, 65504.0, 2f32.powi(-24)] {
+            assert_eq!(f16_to_f32(f32_to_f16(value)), value);
+        }
+        assert_eq!(f32_to_f16(1.0e6), 0x7c00);
+    }
+}
@@ -1,91 +1,239 @@
 use crate::{CONFIG, utils::copy, modules::config::does_config_exist};
-use anyhow::Result;
-use filetime::FileTime;
+use anyhow::{Context, Result};
+use itertools::Itertools;
 use log::{error, info, trace};
+use merkle_hash::blake3;
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
-    env::current_dir,
-    fs::{self, metadata},
-    path::PathBuf,
+    collections::{BTreeMap, HashMap, HashSet},
+    env::{self, current_dir},
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
     process::exit,
-    sync::Arc,
 };
 
-use super::{config::TexUnit, dds::build_from_tex_unit};
+use super::{
+    cache::{cache_dir, prune_to},
+    config::{BuildProfile, TexUnit},
+    dds::build_from_tex_unit,
+    manifest,
+    variants::variant_textures,
+};
+
+/// Profile used when none is given, and the environment variable selecting another
+const DEFAULT_PROFILE: &str = "release";
+pub const PROFILE_VAR: &str = "GBT_PROFILE";
+
+/// Fingerprints of the cached textures, so only the TexUnits whose sources or
+/// settings changed since they were last built are rebuilt
+#[derive(Serialize, Deserialize, Default, Debug)]
+struct BuildState {
+    // Key -> Cache file relative to Textures/Cache (e.g. release/FurinaBodyDiffuse.dds)
+    // Value -> Fingerprint it was built from
+    #[serde(default)]
+    textures: BTreeMap<String, String>,
+}
+
+impl BuildState {
+    fn path() -> PathBuf {
+        PathBuf::from("./.gbt/build-state.json")
+    }
+
+    /// Loads the build state, starting over if it is missing or unreadable
+    fn load() -> Self {
+        fs::read_to_string(Self::path())
+            .ok()
+            .and_then(|f| serde_json::from_str(&f).ok())
+            .unwrap_or_default()
+    }
 
-fn build_texture_units(force: bool, textures: HashMap<String, TexUnit>) -> Result<Vec<()>> {
-    let cache_path = current_dir().unwrap().join("Textures/Cache");
+    fn save(&self) -> Result<()> {
+        fs::create_dir_all(Self::path().parent().unwrap())?;
+        fs::write(Self::path(), serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Hashes the settings a TexUnit is built with, and the contents of its sources
+fn fingerprint(tex_unit: &TexUnit, profile: &BuildProfile) -> Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(serde_json::to_string(tex_unit)?.as_bytes());
+    hasher.update(serde_json::to_string(profile)?.as_bytes());
+    for path in tex_unit.sources() {
+        let mut file = File::open(&path).with_context(|| format!("Missing Source {:}", path.display()))?;
+        io::copy(&mut file, &mut hasher).with_context(|| format!("Failed to Read {:}", path.display()))?;
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Builds the TexUnits that changed, returning the fingerprints of every one
+/// that is now up to date
+fn build_texture_units(
+    force: bool,
+    textures: &HashMap<String, TexUnit>,
+    profile_name: &str,
+    profile: &BuildProfile,
+    state: &BuildState,
+    global_cache: Option<&Path>,
+) -> Vec<Result<(String, String)>> {
+    // Each profile keeps its own cache, so switching between them does not rebuild
+    let cache_path = current_dir().unwrap().join("Textures/Cache").join(profile_name);
     let output_path = current_dir().unwrap().join("Output");
-    fs::create_dir_all(&cache_path)?;
-    fs::create_dir_all(&output_path)?;
+    if let Err(err) = fs::create_dir_all(&cache_path).and(fs::create_dir_all(&output_path)) {
+        return vec![Err(err.into())];
+    }
     textures
         .par_iter()
         .map(|(filename, texunit)| {
+            let key = format!("{:}/{:}.dds", profile_name, filename);
             let cache_file_name = cache_path.join(format!("{:}.dds", filename));
             let output_file_name = output_path.join(format!("{:}.dds", filename));
+            let fingerprint = fingerprint(texunit, profile).with_context(|| format!("Failed to Build {:}", filename))?;
             //Check for updates or force
-            if force || needs_rebuild(texunit.paths.clone(), cache_file_name.clone()) {
-                build_from_tex_unit(texunit.clone(), cache_file_name.clone())?;
+            if force || !cache_file_name.exists() || state.textures.get(&key) != Some(&fingerprint) {
+                build_from_tex_unit(texunit.clone(), cache_file_name.clone(), profile, global_cache)
+                    .with_context(|| format!("Failed to Build {:}", filename))?;
             } else {
                 trace!("{:} does not need rebuild", filename);
             }
             copy::copy(&cache_file_name, &output_file_name, false)?;
-            Ok(())
+            Ok((key, fingerprint))
         })
-        .collect::<Result<Vec<_>, _>>()
+        .collect()
 }
 
-pub fn build(force: bool) {
+pub fn build(force: bool, profile: Option<&str>) {
     if !does_config_exist() {
         error!("Config does not exist. Exiting....");
         exit(1);
     }
-    let textures = CONFIG.lock().unwrap().model_textures.clone();
-    let res = build_texture_units(force, textures);
-    if res.is_err() {
-        error!("Failed to Compile Model Texture Units");
+    let profile_name = profile
+        .map(|f| f.to_owned())
+        .or_else(|| env::var(PROFILE_VAR).ok())
+        .unwrap_or(DEFAULT_PROFILE.to_owned());
+    let Some(profile) = CONFIG.lock().unwrap().profiles.get(&profile_name).cloned() else {
+        error!(
+            "Build Profile {:} does not exist. Available Profiles: {:}",
+            profile_name,
+            CONFIG.lock().unwrap().profiles.keys().sorted().join(", ")
+        );
         exit(1);
+    };
+    info!("Building with the {:} Profile", profile_name);
+
+    let mut state = BuildState::load();
+    let mut built = HashSet::new();
+    let mut failed = false;
+    let (groups, global_cache) = {
+        let config = CONFIG.lock().unwrap();
+        (
+            [
+                ("Model", config.model_textures.clone()),
+                ("Texture Mod", config.texture_textures.clone()),
+                ("Variant", variant_textures(&config.variants)),
+            ],
+            config.global_cache.clone(),
+        )
+    };
+    let global_cache_dir = global_cache.as_ref().map(cache_dir);
+    for (group, textures) in groups {
+        let results = build_texture_units(
+            force,
+            &textures,
+            &profile_name,
+            &profile,
+            &state,
+            global_cache_dir.as_deref(),
+        );
+        let errors = results
+            .into_iter()
+            .filter_map(|res| match res {
+                Ok((key, fingerprint)) => {
+                    built.insert(key.to_owned());
+                    state.textures.insert(key, fingerprint);
+                    None
+                }
+                Err(err) => Some(err),
+            })
+            .collect::<Vec<_>>();
+        if !errors.is_empty() {
+            errors.iter().for_each(|err| error!("{:#}", err));
+            error!("Failed to Compile {:} Texture Units", group);
+            failed = true;
+        }
     }
-    let textures = CONFIG.lock().unwrap().texture_textures.clone();
-    let res = build_texture_units(force, textures);
-    if res.is_err() {
-        error!("Failed to Compile Texture Mod Texture Units");
-        exit(1);
+    // Forget TexUnits of this profile that no longer exist
+    if !failed {
+        let prefix = format!("{:}/", profile_name);
+        state
+            .textures
+            .retain(|key, _| !key.starts_with(&prefix) || built.contains(key));
     }
-    info!("Rebuild Complete");
-}
-
-fn needs_rebuild(source: Arc<[PathBuf]>, target: PathBuf) -> bool {
-    if !target.exists() {
-        return true;
+    if let Err(err) = state.save() {
+        error!("Failed to Save Build State: {:#}", err);
     }
-    let tmeta = metadata(target).expect("Failed to get File Metadata");
-    let tfile_mtime = FileTime::from_last_modification_time(&tmeta);
-    let res: Result<Vec<_>, _> = source
-        .par_iter()
-        .map(|f| {
-            let imeta = metadata(f).expect("Failed to get File Metadata");
-            let ifile_mtime = FileTime::from_last_modification_time(&imeta);
-            if tfile_mtime.gt(&ifile_mtime) {
-                Ok(())
-            } else {
-                Err(())
-            }
-        })
-        .collect();
-    if res.is_err() {
-        return true;
+    let outputs = built
+        .iter()
+        .filter_map(|f| f.split_once('/'))
+        .map(|(_, file)| file.to_owned());
+    if let Err(err) = manifest::record("build", outputs, !failed) {
+        error!("{:#}", err);
+    }
+    if let (Some(cache), Some(dir)) = (&global_cache, &global_cache_dir) {
+        if let Err(err) = prune_to(dir, cache.max_size.saturating_mul(1024 * 1024)) {
+            error!("Failed to Prune Global Cache: {:#}", err);
+        }
+    }
+    if failed {
+        exit(1);
     }
-    return false;
+    info!("Rebuild Complete");
 }
 
 #[macro_export]
 macro_rules! build {
     () => {
-        crate::modules::build::build(false)
+        $crate::modules::build::build(false, None)
     };
     ($a: expr) => {
-        crate::modules::build::build($a)
+        $crate::modules::build::build($a, None)
+    };
+    ($a: expr, $b: expr) => {
+        $crate::modules::build::build($a, $b)
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_fingerprint() {
+        let tmp = tempfile::tempdir().unwrap();
+        let source = tmp.path().join("Body.png");
+        fs::write(&source, "original").unwrap();
+        let mut tex_unit: TexUnit = serde_yaml::from_str(&format!(
+            "{{ paths: [{:?}], encoding: BC7Unorm }}",
+            source
+        ))
+        .unwrap();
+        let profile: BuildProfile = serde_yaml::from_str("quality: slow").unwrap();
+        let dev: BuildProfile = serde_yaml::from_str("{ quality: fast, mipmaps: none }").unwrap();
+        let original = fingerprint(&tex_unit, &profile).unwrap();
+        assert_eq!(fingerprint(&tex_unit, &profile).unwrap(), original);
+
+        fs::write(&source, "edited").unwrap();
+        let edited = fingerprint(&tex_unit, &profile).unwrap();
+        assert_ne!(edited, original);
+
+        tex_unit.mip_count = 4;
+        assert_ne!(fingerprint(&tex_unit, &profile).unwrap(), edited);
+        tex_unit.mip_count = 1;
+        assert_ne!(fingerprint(&tex_unit, &dev).unwrap(), edited);
+
+        fs::remove_file(&source).unwrap();
+        assert!(fingerprint(&tex_unit, &profile).is_err());
+    }
+}
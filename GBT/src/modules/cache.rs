@@ -0,0 +1,164 @@
+use anyhow::{Context, Result};
+use image::RgbaImage;
+use log::{error, info, trace};
+use merkle_hash::blake3;
+use std::{
+    env,
+    fs::{self, File},
+    path::{Path, PathBuf},
+    process::{self, exit},
+    thread,
+    time::SystemTime,
+};
+use walkdir::WalkDir;
+
+use crate::CONFIG;
+
+use super::config::GlobalCache;
+
+/// Folder of the global cache, overridden by `path` in the Config
+pub fn cache_dir(cache: &GlobalCache) -> PathBuf {
+    if let Some(path) = &cache.path {
+        return path.to_owned();
+    }
+    let base = env::var_os("LOCALAPPDATA")
+        .or_else(|| env::var_os("XDG_CACHE_HOME"))
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|f| PathBuf::from(f).join(".cache")))
+        .unwrap_or_else(env::temp_dir);
+    base.join("gbt").join("Textures")
+}
+
+/// Hashes the composed image and everything it is encoded with. The version is
+/// part of it, so textures from an older encoder are not reused
+pub fn key(image: &RgbaImage, params: &str) -> String {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(env!("CARGO_PKG_VERSION").as_bytes());
+    hasher.update(params.as_bytes());
+    hasher.update(&image.width().to_le_bytes());
+    hasher.update(&image.height().to_le_bytes());
+    hasher.update(image.as_raw());
+    hasher.finalize().to_hex().to_string()
+}
+
+fn entry(dir: &Path, key: &str) -> PathBuf {
+    dir.join(&key[..2]).join(format!("{:}.dds", key))
+}
+
+/// Copies the cached texture for `key` to `target`, returning whether there was one
+pub fn fetch(dir: &Path, key: &str, target: &Path) -> bool {
+    let entry = entry(dir, key);
+    if fs::copy(&entry, target).is_err() {
+        return false;
+    }
+    // Marks the texture as recently used
+    let _ = File::options()
+        .write(true)
+        .open(&entry)
+        .and_then(|f| f.set_modified(SystemTime::now()));
+    trace!("Reused {:}", entry.display());
+    true
+}
+
+/// Adds `source` to the cache. Entries are staged and renamed into place, so
+/// builds running at the same time never see half written textures
+pub fn store(dir: &Path, key: &str, source: &Path) -> Result<()> {
+    let entry = entry(dir, key);
+    fs::create_dir_all(entry.parent().unwrap())?;
+    let staged = entry.with_extension(format!(
+        "{:}-{:?}.tmp",
+        process::id(),
+        thread::current().id()
+    ));
+    fs::copy(source, &staged)
+        .and_then(|_| fs::rename(&staged, &entry))
+        .with_context(|| format!("Failed to Cache {:}", source.display()))
+        .inspect_err(|_| drop(fs::remove_file(&staged)))?;
+    Ok(())
+}
+
+/// Removes the least recently used textures until the cache fits in `max_size`
+/// bytes. Returns the number of textures and bytes removed
+pub fn prune_to(dir: &Path, max_size: u64) -> Result<(usize, u64)> {
+    let mut entries = WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|f| f.ok())
+        .filter(|f| f.file_type().is_file())
+        .filter_map(|f| {
+            let metadata = f.metadata().ok()?;
+            Some((metadata.modified().ok()?, metadata.len(), f.into_path()))
+        })
+        .collect::<Vec<_>>();
+    entries.sort();
+    let mut size = entries.iter().map(|f| f.1).sum::<u64>();
+    let (mut removed, mut freed) = (0, 0);
+    for (_, len, path) in entries {
+        if size <= max_size {
+            break;
+        }
+        fs::remove_file(&path).with_context(|| format!("Failed to Remove {:}", path.display()))?;
+        size -= len;
+        removed += 1;
+        freed += len;
+    }
+    Ok((removed, freed))
+}
+
+pub fn prune(max_size: Option<u64>) {
+    let cache = CONFIG
+        .lock()
+        .unwrap()
+        .global_cache
+        .to_owned()
+        .unwrap_or_default();
+    let dir = cache_dir(&cache);
+    let max_size = max_size.unwrap_or(cache.max_size);
+    match prune_to(&dir, max_size.saturating_mul(1024 * 1024)) {
+        Ok((removed, freed)) => info!(
+            "Pruned {:} Textures ({:} MB) from {:}",
+            removed,
+            freed / 1024 / 1024,
+            dir.display()
+        ),
+        Err(err) => {
+            error!("{:#}", err);
+            exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    pub fn test_global_cache() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let image = RgbaImage::new(4, 4);
+        let (bc7, bc1) = (key(&image, "BC7Unorm"), key(&image, "BC1Unorm"));
+        assert_ne!(bc7, bc1);
+
+        let built = dir.join("Built.dds");
+        fs::write(&built, [0; 16]).unwrap();
+        let target = dir.join("Target.dds");
+        assert!(!fetch(dir, &bc7, &target));
+        store(dir, &bc7, &built).unwrap();
+        store(dir, &bc1, &built).unwrap();
+        assert!(fetch(dir, &bc7, &target));
+        assert_eq!(fs::read(&target).unwrap(), [0; 16]);
+
+        // The least recently used texture goes first
+        File::options()
+            .write(true)
+            .open(entry(dir, &bc1))
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(60))
+            .unwrap();
+        fs::remove_file(&built).unwrap();
+        fs::remove_file(&target).unwrap();
+        assert_eq!(prune_to(dir, 16).unwrap(), (1, 16));
+        assert!(entry(dir, &bc7).exists());
+    }
+}
@@ -0,0 +1,31 @@
+use log::{error, info};
+use std::{path::PathBuf, process::exit};
+
+use crate::CONFIG;
+
+use super::{
+    config::{does_config_exist, ProjectType},
+    hash::{load_components, validate_components},
+};
+
+pub fn check() {
+    if !does_config_exist() {
+        error!("Config does not exist. Exiting....");
+        exit(1);
+    }
+    let project_types = CONFIG.lock().unwrap().project_type.to_owned();
+    let mut errors = vec![];
+    if project_types.contains(&ProjectType::Model) {
+        let source = PathBuf::from("./Source/Model");
+        match load_components(&source) {
+            Ok(components) => errors.extend(validate_components(&source, &components)),
+            Err(err) => errors.push(err),
+        }
+    }
+    if !errors.is_empty() {
+        errors.iter().for_each(|err| error!("{err}"));
+        error!("Check Failed with {:} Errors", errors.len());
+        exit(1);
+    }
+    info!("Check Passed");
+}
@@ -0,0 +1,241 @@
+use anyhow::{Context, Result};
+use itertools::Itertools;
+use log::{error, info, warn};
+use normpath::PathExt;
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    process::exit,
+};
+use walkdir::WalkDir;
+
+use crate::CONFIG;
+
+use super::{
+    config::does_config_exist,
+    manifest::{self, Manifest},
+    variants::variant_textures,
+};
+
+/// Folders holding the user's sources, which are never cleaned
+const PROTECTED: [&str; 2] = ["./Textures/Model", "./Source"];
+
+/// Files GBT generates into Output
+const GENERATED: [&str; 4] = ["dds", "buf", "ib", "ini"];
+
+fn is_protected(path: &Path) -> bool {
+    let Ok(path) = path.normalize() else {
+        return false;
+    };
+    PROTECTED.iter().any(|f| {
+        Path::new(f)
+            .normalize()
+            .is_ok_and(|protected| path.starts_with(protected))
+    })
+}
+
+fn files(dir: &Path) -> impl Iterator<Item = PathBuf> {
+    WalkDir::new(dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|f| f.ok())
+        .filter(|f| f.file_type().is_file())
+        .map(|f| f.into_path())
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .is_some_and(|ext| extensions.iter().any(|f| ext.eq_ignore_ascii_case(f)))
+}
+
+/// Files generated into `output`, including the shaders and resources copied there
+fn generated_output(output: &Path, copied: &HashSet<String>) -> Vec<PathBuf> {
+    files(output)
+        .filter(|f| {
+            has_extension(f, &GENERATED)
+                || f.file_name()
+                    .is_some_and(|name| copied.contains(&*name.to_string_lossy()))
+        })
+        .collect()
+}
+
+/// Textures in the cache or `output` that no TexUnit builds anymore. Textures
+/// cached directly under `cache` predate build profiles and are always orphaned
+fn find_orphans(
+    cache: &Path,
+    output: &Path,
+    textures: &HashSet<String>,
+    copied: &HashSet<String>,
+) -> Vec<PathBuf> {
+    let is_built = |path: &Path| {
+        path.file_stem()
+            .is_some_and(|f| textures.contains(&*f.to_string_lossy()))
+    };
+    let cached = files(cache)
+        .filter(|f| has_extension(f, &["dds"]) && (f.parent() == Some(cache) || !is_built(f)));
+    let outputs = fs::read_dir(output)
+        .map(|dir| {
+            dir.filter_map(|f| f.ok())
+                .map(|f| f.path())
+                .filter(|f| f.is_file() && has_extension(f, &["dds"]) && !is_built(f))
+                .filter(|f| {
+                    !f.file_name()
+                        .is_some_and(|name| copied.contains(&*name.to_string_lossy()))
+                })
+                .sorted()
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    cached.chain(outputs).collect()
+}
+
+fn remove(path: &Path, dry_run: bool) -> Result<()> {
+    if is_protected(path) {
+        warn!("Refusing to Remove {:}", path.display());
+        return Ok(());
+    }
+    if dry_run {
+        info!("Would Remove {:}", path.display());
+        return Ok(());
+    }
+    let metadata = fs::symlink_metadata(path)?;
+    if metadata.is_symlink() {
+        // Only the link goes, never what it points to
+        fs::remove_file(path).or_else(|_| fs::remove_dir(path))
+    } else if metadata.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+    .with_context(|| format!("Failed to Remove {:}", path.display()))?;
+    info!("Removed {:}", path.display());
+    Ok(())
+}
+
+pub fn clean(cache: bool, output: bool, linked: bool, orphans: bool, dry_run: bool) {
+    if !does_config_exist() {
+        error!("Config does not exist. Exiting....");
+        exit(1);
+    }
+    let (project_name, migoto_path, textures, copied) = {
+        let config = CONFIG.lock().unwrap();
+        let textures = config
+            .model_textures
+            .keys()
+            .chain(config.texture_textures.keys())
+            .cloned()
+            .chain(variant_textures(&config.variants).into_keys())
+            .collect::<HashSet<_>>();
+        let copied = config
+            .shaders
+            .values()
+            .flat_map(|f| std::iter::once(&f.path).chain(f.resources.values()))
+            .filter_map(|f| f.file_name())
+            .map(|f| f.to_string_lossy().to_string())
+            .collect::<HashSet<_>>();
+        (
+            config.project_name.to_owned(),
+            config.migoto_path.to_owned(),
+            textures,
+            copied,
+        )
+    };
+    let cache_path = PathBuf::from("./Textures/Cache");
+    let output_path = PathBuf::from("./Output");
+
+    let mut targets = vec![];
+    if cache {
+        targets.extend([
+            cache_path.to_owned(),
+            PathBuf::from("./.gbt/build-state.json"),
+        ]);
+    }
+    if output {
+        targets.extend(generated_output(&output_path, &copied));
+    }
+    if orphans {
+        targets.extend(find_orphans(&cache_path, &output_path, &textures, &copied));
+        // Anything no generating step recorded is not shipped either
+        if let Some(manifest) = Manifest::load() {
+            targets.extend(manifest.unlisted(&output_path));
+        }
+    }
+    if linked {
+        match migoto_path {
+            Some(migoto_path) => targets.push(migoto_path.join("Mods").join(project_name)),
+            None => warn!("Migoto Path Not Set. Skipping Linked Mod"),
+        }
+    }
+
+    let targets = targets
+        .into_iter()
+        .filter(|f| f.symlink_metadata().is_ok())
+        .unique()
+        .collect::<Vec<_>>();
+    let mut failed = false;
+    for target in &targets {
+        // Files of a folder that was already removed
+        if !dry_run && target.symlink_metadata().is_err() {
+            continue;
+        }
+        if let Err(err) = remove(target, dry_run) {
+            error!("{:#}", err);
+            failed = true;
+        }
+    }
+    let removed = targets
+        .iter()
+        .filter(|f| f.starts_with(&output_path) && f.symlink_metadata().is_err())
+        .cloned()
+        .collect::<Vec<_>>();
+    if !removed.is_empty() {
+        if let Err(err) = manifest::forget(&output_path, &removed) {
+            error!("{:#}", err);
+            failed = true;
+        }
+    }
+    if failed {
+        exit(1);
+    }
+    match dry_run {
+        true => info!("Would Remove {:} Paths", targets.len()),
+        false => info!("Clean Complete"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_find_orphans() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let (cache, output) = (root.join("Cache"), root.join("Output"));
+        fs::create_dir_all(cache.join("release")).unwrap();
+        fs::create_dir_all(&output).unwrap();
+        for file in [
+            cache.join("Body.dds"),
+            cache.join("release/Body.dds"),
+            cache.join("release/Renamed.dds"),
+            output.join("Body.dds"),
+            output.join("Renamed.dds"),
+            output.join("ramp.dds"),
+            output.join("Mod.ini"),
+        ] {
+            fs::write(file, "").unwrap();
+        }
+        let textures = HashSet::from(["Body".to_owned()]);
+        let copied = HashSet::from(["ramp.dds".to_owned()]);
+        assert_eq!(
+            find_orphans(&cache, &output, &textures, &copied),
+            vec![
+                cache.join("Body.dds"),
+                cache.join("release/Renamed.dds"),
+                output.join("Renamed.dds"),
+            ]
+        );
+        assert_eq!(generated_output(&output, &HashSet::new()).len(), 4);
+    }
+}
@@ -1,12 +1,13 @@
 use anyhow::Result;
 use derivative::Derivative;
-use image_dds::ImageFormat;
+use image_dds::{ImageFormat, Mipmaps, Quality};
 use lazy_static::lazy_static;
 use log::{error, info, trace};
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize};
 
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Display,
     fs::File,
     io::{Read, Write},
     path::PathBuf,
@@ -88,6 +89,29 @@ impl From<ImageFormat> for DDSFormat {
     }
 }
 
+impl DDSFormat {
+    /// Whether the format is encoded in 4x4 blocks
+    pub fn is_block_compressed(&self) -> bool {
+        matches!(
+            self,
+            Self::BC1Unorm
+                | Self::BC1Srgb
+                | Self::BC2Unorm
+                | Self::BC2Srgb
+                | Self::BC3Unorm
+                | Self::BC3Srgb
+                | Self::BC4Unorm
+                | Self::BC4Snorm
+                | Self::BC5Unorm
+                | Self::BC5Snorm
+                | Self::BC6Ufloat
+                | Self::BC6Sfloat
+                | Self::BC7Unorm
+                | Self::BC7Srgb
+        )
+    }
+}
+
 impl Into<ImageFormat> for DDSFormat {
     fn into(self) -> ImageFormat {
         match self {
@@ -115,10 +139,352 @@ impl Into<ImageFormat> for DDSFormat {
     }
 }
 
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum MipmapMode {
+    #[serde(rename = "none")]
+    Disabled,
+    #[serde(rename = "original")]
+    Original,
+    #[serde(rename = "full")]
+    Full,
+}
+
+/// Channels read from a source image, written as `path` or `path:RGB`.
+/// `L` reads the luminance of the image
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+#[serde(try_from = "String", into = "String")]
+pub struct ChannelSource {
+    pub path: PathBuf,
+    // Same as the channels written when not given
+    pub channels: Option<String>,
+}
+
+impl TryFrom<String> for ChannelSource {
+    type Error = String;
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        // Only a trailing run of channel letters is split off, so drive letters are kept
+        match value.rsplit_once(':') {
+            Some((path, channels))
+                if !path.is_empty()
+                    && (1..=4).contains(&channels.len())
+                    && channels.chars().all(|c| "RGBAL".contains(c)) =>
+            {
+                Ok(Self {
+                    path: PathBuf::from(path),
+                    channels: Some(channels.to_owned()),
+                })
+            }
+            _ if value.is_empty() => Err("Channel Source is missing a Path".to_owned()),
+            _ => Ok(Self {
+                path: PathBuf::from(value),
+                channels: None,
+            }),
+        }
+    }
+}
+
+impl From<ChannelSource> for String {
+    fn from(value: ChannelSource) -> Self {
+        match value.channels {
+            Some(channels) => format!("{:}:{:}", value.path.display(), channels),
+            None => value.path.display().to_string(),
+        }
+    }
+}
+
+/// Index of a channel letter in an RGBA pixel
+pub fn channel_index(channel: char) -> Option<usize> {
+    "RGBA".find(channel)
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BlendMode {
+    #[default]
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Add,
+    Darken,
+    Lighten,
+}
+
+/// Image composited over the RGB of a TexUnit when it is built
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Layer {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub blend: BlendMode,
+    #[serde(default = "default_opacity")]
+    pub opacity: f32,
+    // Limits the Layer to where the mask is white. Reads the luminance by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mask: Option<ChannelSource>,
+    // Degrees the hue of the Layer is rotated by
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hue: Option<f32>,
+    // Factor the saturation of the Layer is scaled by
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub saturation: Option<f32>,
+}
+
+fn default_opacity() -> f32 {
+    1.0
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct TexUnit {
+    // Sources of a Unit without explicit channels
+    #[serde(default, skip_serializing_if = "is_empty")]
     pub paths: Arc<[PathBuf]>,
+    // Key -> Channels written (e.g. RGB)
+    // Value -> Source they are read from (e.g. ./Textures/Model/FurinaBodyDiffuseAlpha.png:L)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub channels: BTreeMap<String, ChannelSource>,
+    // Composited in order over the image built from the channels
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub layers: Vec<Layer>,
     pub encoding: DDSFormat,
+    // Width and height of the DDS the Unit was generated from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dimensions: Option<[u32; 2]>,
+    // Resizes sources of another size to the dimensions instead of failing the build
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub resample: bool,
+    // Mip levels of the DDS the Unit was generated from. Older projects did not record it
+    #[serde(default = "default_mip_count")]
+    pub mip_count: u32,
+    // Overrides the mips that are generated. Matches the original by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mipmaps: Option<MipmapMode>,
+}
+
+fn default_mip_count() -> u32 {
+    1
+}
+
+fn is_empty(paths: &Arc<[PathBuf]>) -> bool {
+    paths.is_empty()
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+fn deserialize_compression_level<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<i32>, D::Error> {
+    match Option::<i32>::deserialize(deserializer)? {
+        Some(level) if !(0..=9).contains(&level) => Err(de::Error::custom(format!(
+            "Compression Level {:} is not between 0 and 9",
+            level
+        ))),
+        level => Ok(level),
+    }
+}
+
+/// Merges the configured profiles over the built-in ones, so `dev` and `release`
+/// always exist for `gbt watch` and `gbt export`
+fn deserialize_profiles<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashMap<String, BuildProfile>, D::Error> {
+    let mut profiles = default_profiles();
+    profiles.extend(HashMap::<String, BuildProfile>::deserialize(deserializer)?);
+    Ok(profiles)
+}
+
+fn default_split_rules() -> BTreeMap<String, SplitRule> {
+    DEFAULT_SPLIT_RULES.clone()
+}
+
+fn default_profiles() -> HashMap<String, BuildProfile> {
+    DEFAULT_PROFILES.clone()
+}
+
+impl TexUnit {
+    pub fn mipmaps(&self, profile: &BuildProfile) -> Mipmaps {
+        match profile.mipmaps.or(self.mipmaps).unwrap_or(MipmapMode::Original) {
+            MipmapMode::Disabled => Mipmaps::Disabled,
+            MipmapMode::Original => Mipmaps::GeneratedExact(self.mip_count),
+            MipmapMode::Full => Mipmaps::GeneratedAutomatic,
+        }
+    }
+
+    /// Channels the Unit is composed from, the widest first so single channels
+    /// override them. Units without explicit channels read the RGB of their images
+    /// and the A of the one named Alpha, as older projects were built
+    pub fn channel_rules(&self) -> Vec<(String, ChannelSource)> {
+        let mut rules = match self.channels.is_empty() {
+            false => self
+                .channels
+                .iter()
+                .map(|(target, source)| (target.to_owned(), source.to_owned()))
+                .collect::<Vec<_>>(),
+            true if self.paths.len() == 1 => vec![(
+                "RGBA".to_owned(),
+                ChannelSource { path: self.paths[0].to_owned(), channels: None },
+            )],
+            true => self
+                .paths
+                .iter()
+                .map(|path| {
+                    match path.file_stem().is_some_and(|f| f.to_string_lossy().contains("Alpha")) {
+                        true => (
+                            "A".to_owned(),
+                            ChannelSource { path: path.to_owned(), channels: Some("L".to_owned()) },
+                        ),
+                        false => (
+                            "RGB".to_owned(),
+                            ChannelSource { path: path.to_owned(), channels: None },
+                        ),
+                    }
+                })
+                .collect(),
+        };
+        rules.sort_by_key(|(target, _)| std::cmp::Reverse(target.len()));
+        rules
+    }
+
+    /// Every file the Unit is built from
+    pub fn sources(&self) -> Vec<PathBuf> {
+        let mut sources = self.paths.to_vec();
+        let layers = self
+            .layers
+            .iter()
+            .flat_map(|f| std::iter::once(&f.path).chain(f.mask.as_ref().map(|f| &f.path)));
+        for path in self.channels.values().map(|f| &f.path).chain(layers) {
+            if !sources.contains(path) {
+                sources.push(path.to_owned());
+            }
+        }
+        sources
+    }
+}
+
+// Key -> Suffix of the split image (e.g. Flat)
+// Value -> Channels of the dumped Texture written to it (e.g. RGB)
+pub type SplitRule = BTreeMap<String, String>;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum EncodeQuality {
+    Fast,
+    Normal,
+    Slow,
+}
+
+impl From<EncodeQuality> for Quality {
+    fn from(value: EncodeQuality) -> Self {
+        match value {
+            EncodeQuality::Fast => Quality::Fast,
+            EncodeQuality::Normal => Quality::Normal,
+            EncodeQuality::Slow => Quality::Slow,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct BuildProfile {
+    pub quality: EncodeQuality,
+    // Overrides the mipmaps of every TexUnit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mipmaps: Option<MipmapMode>,
+}
+
+/// Encoded textures shared by every project of the user
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct GlobalCache {
+    // Defaults to the user's cache folder
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    // Megabytes kept before the least recently used textures are pruned
+    #[serde(default = "default_cache_size")]
+    pub max_size: u64,
+}
+
+fn default_cache_size() -> u64 {
+    2048
+}
+
+impl Default for GlobalCache {
+    fn default() -> Self {
+        Self { path: None, max_size: default_cache_size() }
+    }
+}
+
+#[derive(Serialize, Deserialize, Hash, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ShaderStage {
+    #[serde(rename = "vs")]
+    Vertex,
+    #[serde(rename = "ps")]
+    Pixel,
+}
+
+impl FromStr for ShaderStage {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "vs" => Ok(ShaderStage::Vertex),
+            "ps" => Ok(ShaderStage::Pixel),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for ShaderStage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ShaderStage::Vertex => write!(f, "vs"),
+            ShaderStage::Pixel => write!(f, "ps"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ShaderUnit {
+    pub stage: ShaderStage,
+    pub path: PathBuf,
+    // Key -> Texture Slot
+    // Value -> Resource bound to the Slot
+    #[serde(default)]
+    pub resources: BTreeMap<u32, PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyType {
+    #[default]
+    Cycle,
+    Toggle,
+    Hold,
+}
+
+impl Display for KeyType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyType::Cycle => write!(f, "cycle"),
+            KeyType::Toggle => write!(f, "toggle"),
+            KeyType::Hold => write!(f, "hold"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct VariantOption {
+    pub name: String,
+    // Key -> Texture replaced while the Option is active
+    // Value -> TexUnit built in its place
+    #[serde(default)]
+    pub textures: BTreeMap<String, TexUnit>,
+    // Model Parts (e.g. FurinaHead) only drawn while the Option is active
+    #[serde(default)]
+    pub parts: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Variant {
+    // 3DMigoto Key Binding (e.g. VK_F6)
+    pub key: String,
+    #[serde(rename = "type", default)]
+    pub key_type: KeyType,
+    pub options: Vec<VariantOption>,
 }
 
 lazy_static! {
@@ -129,6 +495,26 @@ lazy_static! {
         m
     };
     static ref DEFAULT_EXE: Exectuable = Exectuable::new();
+    static ref DEFAULT_PROFILES: HashMap<String, BuildProfile> = {
+        let mut m = HashMap::new();
+        m.insert(
+            "dev".to_string(),
+            BuildProfile { quality: EncodeQuality::Fast, mipmaps: Some(MipmapMode::Disabled) },
+        );
+        m.insert("release".to_string(), BuildProfile { quality: EncodeQuality::Slow, mipmaps: None });
+        m
+    };
+    static ref DEFAULT_SPLIT_RULES: BTreeMap<String, SplitRule> = {
+        let flat_alpha = SplitRule::from([
+            ("Flat".to_string(), "RGB".to_string()),
+            ("Alpha".to_string(), "A".to_string()),
+        ]);
+        let mut m = BTreeMap::new();
+        m.insert("".to_string(), SplitRule::from([("Flat".to_string(), "RGBA".to_string())]));
+        m.insert("Diffuse".to_string(), flat_alpha.clone());
+        m.insert("LightMap".to_string(), flat_alpha);
+        m
+    };
 }
 
 
@@ -154,6 +540,29 @@ pub struct Config {
     #[serde(skip)]
     pub scripts_parsed: HashMap<String, ScriptParser>,
 
+    #[serde(rename = "Build Profiles", default = "default_profiles", deserialize_with = "deserialize_profiles")]
+    #[derivative(Default(value = "DEFAULT_PROFILES.clone()"))]
+    pub profiles: HashMap<String, BuildProfile>,
+
+    // Reuses textures encoded by other projects when set
+    #[serde(rename = "Global Cache", default, skip_serializing_if = "Option::is_none")]
+    pub global_cache: Option<GlobalCache>,
+
+    // Deflate level (0-9) of exports and archives
+    #[serde(
+        rename = "Compression Level",
+        default,
+        deserialize_with = "deserialize_compression_level",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub compression_level: Option<i32>,
+
+    // Key -> Suffix of the dumped Textures the Rule applies to. The longest match wins
+    // Value -> Images the Texture is split into when scaffolding
+    #[serde(rename = "Split Rules", default = "default_split_rules")]
+    #[derivative(Default(value = "DEFAULT_SPLIT_RULES.clone()"))]
+    pub split_rules: BTreeMap<String, SplitRule>,
+
     #[serde(rename = "Project Type")]
     pub project_type: HashSet<ProjectType>,
     // Generated By Scaffold
@@ -162,8 +571,13 @@ pub struct Config {
     // Generated By Scaffold
     #[serde(rename = "Textures")]
     pub texture_textures: HashMap<String, TexUnit>,
-    // Key -> Target
-    // Value -> Source
+    // Generated By Scaffold
+    // Key -> Shader Hash
+    #[serde(rename = "Shaders", default)]
+    pub shaders: HashMap<String, ShaderUnit>,
+    // Key -> Variant Name
+    #[serde(rename = "Variants", default)]
+    pub variants: HashMap<String, Variant>,
 }
 
 impl Config {
@@ -1,63 +1,38 @@
-use std::{fs::{File, self}, io::BufWriter, path::PathBuf};
-use anyhow::Result;
-use image::{GrayImage, Luma, RgbaImage};
+use std::{collections::{BTreeMap, HashMap}, fs::{File, self}, io::{BufWriter, Write}, path::{Path, PathBuf}};
+use anyhow::{bail, Context, Result};
+use image::{imageops::{self, FilterType}, ColorType, Rgba, RgbaImage};
 use image_dds::{dds_from_image, dds_image_format};
-use itertools::izip;
-use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
-use log::{trace, info};
-use crate::modules::config::{DDSFormat, TexUnit};
+use log::{trace, info, warn};
+use super::{cache, layers::apply_layers};
+use crate::modules::config::{channel_index, BuildProfile, ChannelSource, DDSFormat, SplitRule, TexUnit};
 
-trait Decompose {
-    fn split_channels(&self) -> [GrayImage; 4];
+/// Luminance of a pixel, matching the grayscale conversion of `image`
+fn luma(pixel: &Rgba<u8>) -> u8 {
+    ((pixel[0] as u32 * 2126 + pixel[1] as u32 * 7152 + pixel[2] as u32 * 722) / 10000) as u8
 }
 
-impl Decompose for RgbaImage {
-    fn split_channels(&self) -> [GrayImage; 4] {
-        let mut red = GrayImage::new(self.width(), self.height());
-        let mut green = red.clone();
-        let mut blue = red.clone();
-        let mut alpha = red.clone();
-        izip!(
-            red.pixels_mut(),
-            green.pixels_mut(),
-            blue.pixels_mut(),
-            alpha.pixels_mut(),
-            self.pixels()
-        )
-        .for_each(|(r, g, b, a, rgba)| {
-            *r = Luma([rgba[0]]);
-            *g = Luma([rgba[1]]);
-            *b = Luma([rgba[2]]);
-            *a = Luma([rgba[3]]);
-        });
-
-        [red, green, blue, alpha]
+pub(super) fn read_channel(pixel: &Rgba<u8>, channel: char) -> u8 {
+    match channel_index(channel) {
+        Some(index) => pixel[index],
+        None => luma(pixel),
     }
 }
 
-trait Compose {
-    fn join_channels(&mut self, channels: [GrayImage; 4]) -> ();
+/// Rule for a dumped Texture, picked by the longest suffix of its name
+fn split_rule<'a>(filename: &str, rules: &'a BTreeMap<String, SplitRule>) -> Option<&'a SplitRule> {
+    rules
+        .iter()
+        .filter(|(suffix, _)| filename.ends_with(suffix.as_str()))
+        .max_by_key(|(suffix, _)| suffix.len())
+        .map(|(_, rule)| rule)
 }
 
-impl Compose for RgbaImage {
-    fn join_channels(&mut self, channels: [GrayImage; 4]) -> () {
-        izip!(
-            channels[0].pixels(),
-            channels[1].pixels(),
-            channels[2].pixels(),
-            channels[3].pixels(),
-            self.pixels_mut()
-        )
-        .for_each(|(r, g, b, a, rgba)| {
-            rgba[0] = r[0];
-            rgba[1] = g[0];
-            rgba[2] = b[0];
-            rgba[3] = a[0];
-        });
-    }
-}
-
-pub fn generate_tex_split(source_dds: PathBuf,  project_path: &PathBuf, target: &PathBuf) -> Result<TexUnit> {
+pub fn generate_tex_split(
+    source_dds: PathBuf,
+    project_path: &Path,
+    target: &Path,
+    rules: &BTreeMap<String, SplitRule>,
+) -> Result<TexUnit> {
     info!("Generating Texture Splits for {:}", &source_dds.file_name().unwrap().to_str().unwrap());
     let filename = source_dds
         .file_stem()
@@ -68,91 +43,179 @@ pub fn generate_tex_split(source_dds: PathBuf,  project_path: &PathBuf, target:
     fs::create_dir_all(&project_path.join(target))?;
     let mut reader = File::open(&source_dds).expect("Failed to Open Image");
     let dds = ddsfile::Dds::read(&mut reader).unwrap();
-    let mut image: RgbaImage = image_dds::image_from_dds(&dds, 0).unwrap();
+    let image: RgbaImage = image_dds::image_from_dds(&dds, 0).unwrap();
     let format = DDSFormat::from(dds_image_format(&dds).expect("Failed to Get DDS Format"));
-    let mut rgba = image.split_channels();
-
-    let mut files = vec![];
-    if filename.contains("Diffuse") || filename.contains("LightMap") {
-        let mut target_file_name = "".to_owned();
-        target_file_name.push_str(&filename);
-        target_file_name.push_str("Alpha");
-        target_file_name.push_str(".png");
-        let alpha = &rgba[3];
-        let target_file_path = project_path.join(target).join(&target_file_name);
-        alpha.save(target_file_path.clone())?;
-        files.push(target.join(target_file_name));
-        drop(target_file_path);
+    let rule = split_rule(&filename, rules)
+        .with_context(|| format!("No Split Rule matches {:}", filename))?;
+
+    let mut channels = BTreeMap::new();
+    for (suffix, written) in rule {
+        let (color, read) = match written.len() {
+            1 => (ColorType::L8, "L"),
+            2 => (ColorType::La8, "LA"),
+            3 => (ColorType::Rgb8, "RGB"),
+            4 => (ColorType::Rgba8, "RGBA"),
+            _ => bail!("Split Rule {:}: {:} must have 1 to 4 Channels", suffix, written),
+        };
+        let indices = written
+            .chars()
+            .map(|f| channel_index(f).with_context(|| format!("Split Rule {:}: {:} is not a Channel", suffix, f)))
+            .collect::<Result<Vec<_>>>()?;
+        let buffer = image
+            .pixels()
+            .flat_map(|pixel| indices.iter().map(|f| pixel[*f]))
+            .collect::<Vec<_>>();
+        let target_file_name = format!("{:}{:}.png", filename, suffix);
+        image::save_buffer(
+            project_path.join(target).join(&target_file_name),
+            &buffer,
+            image.width(),
+            image.height(),
+            color,
+        )?;
+        channels.insert(
+            written.to_owned(),
+            ChannelSource {
+                path: target.join(target_file_name),
+                channels: (read != written).then(|| read.to_owned()),
+            },
+        );
     }
-    let mut target_file_name = "".to_owned();
-    target_file_name.push_str(&filename);
-    target_file_name.push_str("Flat");
-    target_file_name.push_str(".png");
-    rgba[3].pixels_mut().for_each(|p| p[0] = 255);
-    image.join_channels(rgba);
-    let target_file_path =  project_path.join(target).join(&target_file_name);
-    image.save(target_file_path.clone())?;
-    files.push(target.join(target_file_name));
-    drop(target_file_path);
 
     let tex_unit = TexUnit {
+        paths: vec![].into(),
+        channels,
+        layers: vec![],
+        dimensions: Some([image.width(), image.height()]),
+        resample: false,
         encoding: format,
-        paths: files.into(),
+        mip_count: dds.get_num_mipmap_levels(),
+        mipmaps: None,
     };
     trace!("Generated Texture Splits for {:} as {:#?}", &source_dds.file_name().unwrap().to_str().unwrap(), tex_unit);
     Ok(tex_unit)
 }
 
-pub fn build_from_tex_unit(tex_unit: TexUnit, output_file_path: PathBuf) -> Result<()> {
+/// Opens a source of a Texture, which must match its size unless it may be resampled
+pub(super) fn open_sized(path: &Path, width: u32, height: u32, resample: bool) -> Result<RgbaImage> {
+    let image = image::open(path)
+        .with_context(|| format!("Failed to Open {:}", path.display()))?
+        .to_rgba8();
+    if image.dimensions() == (width, height) {
+        return Ok(image);
+    }
+    if !resample {
+        bail!(
+            "{:} is {:}x{:} but the Texture is {:}x{:}. Set `resample: true` on the Texture Unit to resize it",
+            path.display(),
+            image.width(),
+            image.height(),
+            width,
+            height
+        );
+    }
+    warn!(
+        "Resampling {:} from {:}x{:} to {:}x{:}",
+        path.display(),
+        image.width(),
+        image.height(),
+        width,
+        height
+    );
+    Ok(imageops::resize(&image, width, height, FilterType::Lanczos3))
+}
+
+/// Composes the image of a TexUnit from the channels of its sources
+fn compose(tex_unit: &TexUnit) -> Result<RgbaImage> {
+    let rules = tex_unit.channel_rules();
+    let Some((_, first)) = rules.first() else {
+        bail!("Texture Unit has no Sources");
+    };
+    // Units scaffolded before the size was recorded match their first source
+    let (width, height) = match tex_unit.dimensions {
+        Some([width, height]) => (width, height),
+        None => image::image_dimensions(&first.path)
+            .with_context(|| format!("Failed to Open {:}", first.path.display()))?,
+    };
+    let mut images: HashMap<&Path, RgbaImage> = HashMap::new();
+    for (_, source) in &rules {
+        if !images.contains_key(source.path.as_path()) {
+            images.insert(&source.path, open_sized(&source.path, width, height, tex_unit.resample)?);
+        }
+    }
+    let mut composed = RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 255]));
+    for (written, source) in &rules {
+        let image = &images[source.path.as_path()];
+        let targets = written
+            .chars()
+            .map(|f| channel_index(f).with_context(|| format!("{:} is not a Channel to write", f)))
+            .collect::<Result<Vec<_>>>()?;
+        let read = source.channels.as_deref().unwrap_or(written).chars().collect::<Vec<_>>();
+        if read.len() != targets.len() && read.len() != 1 {
+            bail!(
+                "Cannot write {:} Channels from {:}",
+                written,
+                String::from(source.to_owned())
+            );
+        }
+        composed.pixels_mut().zip(image.pixels()).for_each(|(out, pixel)| {
+            for (index, target) in targets.iter().enumerate() {
+                out[*target] = read_channel(pixel, read[index.min(read.len() - 1)]);
+            }
+        });
+    }
+    Ok(composed)
+}
+
+pub fn build_from_tex_unit(
+    tex_unit: TexUnit,
+    output_file_path: PathBuf,
+    profile: &BuildProfile,
+    global_cache: Option<&Path>,
+) -> Result<()> {
     info!("Building From Texture Unit\n {:#?}", &tex_unit);
-    let mut flat = tex_unit
-        .paths
-        .par_iter()
-        .filter(|f| {
-            !f.file_stem()
-                .unwrap()
-                .to_str()
-                .unwrap()
-                .to_string()
-                .contains("Alpha")
-        })
-        .map(|f| image::open(f).unwrap().to_rgba8())
-        .collect::<Vec<_>>()
-        .pop().expect("No Flat Found");
-    if tex_unit.paths.len() > 1 {
-        let alpha = tex_unit
-            .paths
-            .par_iter()
-            .filter(|f| {
-                f.file_stem()
-                    .unwrap()
-                    .to_str()
-                    .unwrap()
-                    .to_string()
-                    .contains("Alpha")
-            })
-            .map(|f| image::open(f).unwrap().to_luma8())
-            .collect::<Vec<_>>()
-            .pop().expect("No Alpha Found");
-        let mut flat_channels = flat.split_channels();
-        flat_channels[3] = alpha;
-        flat.join_channels(flat_channels);
+    let mut image = compose(&tex_unit)?;
+    apply_layers(&mut image, &tex_unit.layers, tex_unit.resample)?;
+    if tex_unit.encoding.is_block_compressed()
+        && !(image.width().is_multiple_of(4) && image.height().is_multiple_of(4))
+    {
+        warn!(
+            "{:?} needs dimensions that are multiples of 4, but {:}x{:} is built to {:#?}",
+            tex_unit.encoding,
+            image.width(),
+            image.height(),
+            output_file_path
+        );
+    }
+    let mipmaps = tex_unit.mipmaps(profile);
+    let cached = global_cache.map(|dir| {
+        let params = format!("{:?}/{:?}/{:?}", tex_unit.encoding, profile.quality, mipmaps);
+        (dir, cache::key(&image, &params))
+    });
+    if let Some((dir, key)) = &cached {
+        if cache::fetch(dir, key, &output_file_path) {
+            info!("Reused {:#?} from the Global Cache", output_file_path);
+            return Ok(());
+        }
     }
     let out_dds = dds_from_image(
-        &flat,
+        &image,
         tex_unit.encoding.into(),
-        image_dds::Quality::Slow,
-        image_dds::Mipmaps::Disabled,
+        profile.quality.into(),
+        mipmaps,
     )?;
     info!("Finished Building. Exporting to {:#?}", output_file_path);
     let mut writer = BufWriter::new(File::create(&output_file_path)?);
     out_dds.write(&mut writer)?;
+    writer.flush()?;
+    if let Some((dir, key)) = &cached {
+        if let Err(err) = cache::store(dir, key, &output_file_path) {
+            warn!("{:#}", err);
+        }
+    }
     Ok(())
 }
 
-
-
-
 pub fn gen_hash_tex_unit(source_dds: PathBuf, project_path: &PathBuf, target: &PathBuf) -> Result<TexUnit>{
     info!("Generating Texture Unit for {:}", &source_dds.file_name().unwrap().to_str().unwrap());
     fs::create_dir_all(&project_path.join(target))?;
@@ -173,8 +236,98 @@ pub fn gen_hash_tex_unit(source_dds: PathBuf, project_path: &PathBuf, target: &P
     image.save(target_file_path.clone())?;
     
     let tex_unit = TexUnit {
+        paths: vec![].into(),
+        channels: BTreeMap::from([(
+            "RGBA".to_owned(),
+            ChannelSource { path: target.join(target_file_name), channels: None },
+        )]),
+        layers: vec![],
+        dimensions: Some([image.width(), image.height()]),
+        resample: false,
         encoding: format,
-        paths: vec![target.join(target_file_name)].into(),
+        mip_count: dds.get_num_mipmap_levels(),
+        mipmaps: None,
     };
     Ok(tex_unit)
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::config::{EncodeQuality, MipmapMode};
+    use image_dds::{ImageFormat, Mipmaps, Quality};
+
+    #[test]
+    pub fn test_split_and_compose() {
+        let tmp = tempfile::tempdir().unwrap();
+        let project = tmp.path();
+        let image = RgbaImage::from_fn(4, 4, |x, y| Rgba([x as u8 * 10, y as u8 * 20, 30, 40 + x as u8]));
+        let dds = dds_from_image(&image, ImageFormat::R8G8B8A8Unorm, Quality::Fast, Mipmaps::Disabled).unwrap();
+        let source = project.join("FurinaLightMap.dds");
+        dds.write(&mut File::create(&source).unwrap()).unwrap();
+
+        let rules = BTreeMap::from([
+            ("".to_owned(), SplitRule::from([("Flat".to_owned(), "RGBA".to_owned())])),
+            (
+                "LightMap".to_owned(),
+                SplitRule::from([
+                    ("Metal".to_owned(), "R".to_owned()),
+                    ("AO".to_owned(), "G".to_owned()),
+                    ("Ramp".to_owned(), "BA".to_owned()),
+                ]),
+            ),
+        ]);
+        let mut tex_unit = generate_tex_split(source, project, &project.join("Textures"), &rules).unwrap();
+        assert_eq!(
+            tex_unit.channels.iter().map(|(k, v)| (k.as_str(), v.channels.as_deref())).collect::<Vec<_>>(),
+            vec![("BA", Some("LA")), ("G", Some("L")), ("R", Some("L"))]
+        );
+        assert!(project.join("Textures/FurinaLightMapAO.png").exists());
+        assert_eq!(tex_unit.dimensions, Some([4, 4]));
+        assert_eq!(compose(&tex_unit).unwrap(), image);
+
+        // Edited sources must keep the size of the original
+        tex_unit.dimensions = Some([8, 8]);
+        assert!(compose(&tex_unit).is_err());
+        tex_unit.resample = true;
+        assert_eq!(compose(&tex_unit).unwrap().dimensions(), (8, 8));
+        tex_unit.dimensions = Some([4, 4]);
+        tex_unit.resample = false;
+
+        // Single channels are written over the wider ones
+        tex_unit.channels.insert(
+            "RGBA".to_owned(),
+            ChannelSource::try_from(format!("{:}", project.join("Textures/FurinaLightMapAO.png").display())).unwrap(),
+        );
+        assert_eq!(compose(&tex_unit).unwrap(), image);
+        tex_unit.channels.insert(
+            "RG".to_owned(),
+            ChannelSource::try_from(format!("{:}:RGB", project.join("Textures/FurinaLightMapMetal.png").display())).unwrap(),
+        );
+        assert!(compose(&tex_unit).is_err());
+    }
+
+    #[test]
+    pub fn test_mipmaps() {
+        let tmp = tempfile::tempdir().unwrap();
+        let project = tmp.path();
+        let image = RgbaImage::from_fn(16, 16, |x, y| Rgba([x as u8 * 16, y as u8 * 16, 0, 255]));
+        let dds = dds_from_image(&image, ImageFormat::R8G8B8A8Unorm, Quality::Fast, Mipmaps::GeneratedExact(3)).unwrap();
+        let source = project.join("FurinaBodyDiffuse.dds");
+        dds.write(&mut File::create(&source).unwrap()).unwrap();
+        let rules = BTreeMap::from([("".to_owned(), SplitRule::from([("Flat".to_owned(), "RGBA".to_owned())]))]);
+        let mut tex_unit = generate_tex_split(source, project, &project.join("Textures"), &rules).unwrap();
+        assert_eq!(tex_unit.mip_count, 3);
+
+        let built_mips = |tex_unit: &TexUnit, mipmaps: Option<MipmapMode>| {
+            let target = project.join("Built.dds");
+            let profile = BuildProfile { quality: EncodeQuality::Fast, mipmaps };
+            build_from_tex_unit(tex_unit.to_owned(), target.to_owned(), &profile, None).unwrap();
+            ddsfile::Dds::read(&mut File::open(target).unwrap()).unwrap().get_num_mipmap_levels()
+        };
+        // The original mip count is kept unless the Unit or the profile overrides it
+        assert_eq!(built_mips(&tex_unit, None), 3);
+        tex_unit.mipmaps = Some(MipmapMode::Full);
+        assert_eq!(built_mips(&tex_unit, None), 5);
+        assert_eq!(built_mips(&tex_unit, Some(MipmapMode::Disabled)), 1);
+    }
+}
@@ -0,0 +1,588 @@
+use anyhow::{Context, Error, Result};
+use itertools::Itertools;
+use log::{error, info, trace, warn};
+use rayon::prelude::*;
+use regex::Regex;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    process::exit,
+};
+
+use crate::{utils::interactions::get_input_string_with_validator, CONFIG};
+
+use super::{
+    buffer::{Fmt, IndexBuffer, InputElement, VertexBuffer, BLEND_STRIDE, POSITION_STRIDE},
+    config::does_config_exist,
+    dds::generate_tex_split,
+    hash::{load_components, Component, TextureHash},
+};
+
+/// Buffers and textures bound by a single draw call of a frame analysis dump
+#[derive(Default, Debug)]
+struct DrawCall {
+    ib: Option<(String, PathBuf)>,
+    vbs: BTreeMap<u32, (String, PathBuf)>,
+    textures: BTreeMap<u32, (String, PathBuf)>,
+}
+
+/// A single VB slot of a draw call, with its elements relative to the slot
+#[derive(Clone)]
+struct Slot {
+    hash: String,
+    stride: usize,
+    elements: Vec<InputElement>,
+    data: Vec<u8>,
+}
+
+fn scan_frame_analysis(frame_analysis: &Path) -> Result<BTreeMap<String, DrawCall>> {
+    // 000123-vb0=1a2b3c4d-vs=...-ps=....txt
+    let re = Regex::new(r"^(\d+)-(ib|vb(\d+)|ps-t(\d+))=([0-9a-fA-F]{8})").unwrap();
+    let mut draws: BTreeMap<String, DrawCall> = BTreeMap::new();
+    for entry in fs::read_dir(frame_analysis)
+        .with_context(|| format!("Failed to Read {:}", frame_analysis.display()))?
+    {
+        let path = entry?.path();
+        let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+        let ext = path
+            .extension()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let Some(captures) = re.captures(&file_name) else {
+            continue;
+        };
+        let draw = draws.entry(captures[1].to_owned()).or_default();
+        let hash = captures[5].to_lowercase();
+        match (captures.get(3), captures.get(4), ext.as_str()) {
+            (None, None, "txt") => draw.ib = Some((hash, path)),
+            (Some(slot), _, "txt") => {
+                draw.vbs.insert(slot.as_str().parse()?, (hash, path));
+            }
+            // jpg dumps are lossy previews that cannot be built into TexUnits
+            (_, Some(slot), "dds") => {
+                draw.textures.insert(slot.as_str().parse()?, (hash, path));
+            }
+            _ => (),
+        }
+    }
+    Ok(draws)
+}
+
+fn read_slot(slot: u32, hash: &str, txt_path: &Path) -> Result<Slot> {
+    let txt = fs::read_to_string(txt_path)
+        .with_context(|| format!("Failed to Read {:}", txt_path.display()))?;
+    let (mut fmt, vertices) = VertexBuffer::parse_txt(&txt)
+        .with_context(|| format!("Failed to Parse {:}", txt_path.display()))?;
+    // Every slot lists the whole input layout, only its own elements are stored in it
+    fmt.elements.retain(|f| f.input_slot == slot);
+    let buf_path = txt_path.with_extension("buf");
+    let data = match buf_path.exists() {
+        true => VertexBuffer::read(&buf_path, fmt.stride)?.data,
+        false => {
+            warn!(
+                "No .buf next to {:}. Falling back to the text dump",
+                txt_path.display()
+            );
+            VertexBuffer::encode(&fmt, &vertices).data
+        }
+    };
+    Ok(Slot {
+        hash: hash.to_owned(),
+        stride: fmt.stride,
+        elements: fmt.elements,
+        data,
+    })
+}
+
+fn has_blend(slot: &Slot) -> bool {
+    slot.elements
+        .iter()
+        .any(|f| f.semantic_name.starts_with("BLEND"))
+}
+
+fn vertex_count(slot: &Slot) -> usize {
+    slot.data.len() / slot.stride
+}
+
+/// Reads the topology from the header of an `ib.txt` dump
+fn topology(txt: &str) -> &str {
+    txt.lines()
+        .take_while(|f| !f.trim().is_empty())
+        .find_map(|f| f.strip_prefix("topology:"))
+        .unwrap_or_default()
+        .trim()
+}
+
+/// Looks for the Position and Blend VBs in the skinning passes of the component.
+/// The game skins them in a pointlist pass bound with the same IB, then draws the
+/// skinned vertices from a different vb0, so the pass is matched by its IB and
+/// the vertex count of the VB it skins into
+fn find_skinning_slots(skinning: &[&DrawCall], vertices: usize) -> Option<(Slot, Slot)> {
+    skinning.iter().find_map(|draw| {
+        let slots = draw
+            .vbs
+            .iter()
+            .map(|(slot, (hash, path))| read_slot(*slot, hash, path))
+            .collect::<Result<Vec<_>>>()
+            .ok()?;
+        let position = slots.first().filter(|f| vertex_count(f) == vertices)?;
+        let blend = slots.iter().skip(1).find(|f| has_blend(f))?;
+        Some((position.to_owned(), blend.to_owned()))
+    })
+}
+
+/// Joins the slots into the single VB layout the Blender plugin and `gen-ini` expect
+fn combine_fmt(slots: &[Slot], ib: &IndexBuffer) -> Fmt {
+    let mut offset = 0;
+    let mut elements = vec![];
+    for slot in slots {
+        elements.extend(slot.elements.iter().map(|element| InputElement {
+            input_slot: 0,
+            aligned_byte_offset: element.aligned_byte_offset + offset,
+            ..element.clone()
+        }));
+        offset += slot.stride;
+    }
+    Fmt {
+        stride: offset,
+        topology: "trianglelist".to_owned(),
+        format: Some(ib.format.clone()),
+        elements,
+    }
+}
+
+/// Keeps only the vertices the part draws, remapping its indices to match
+fn compact_part(slots: &[Slot], ib: &IndexBuffer) -> Result<(Vec<u8>, IndexBuffer)> {
+    let vertex_count = slots
+        .iter()
+        .map(|f| f.data.len() / f.stride)
+        .min()
+        .unwrap_or_default();
+    let mut remap = HashMap::new();
+    let mut order = vec![];
+    let mut indices = vec![];
+    for index in &ib.indices {
+        if *index as usize >= vertex_count {
+            return Err(Error::msg(format!(
+                "Index {:} is out of range of the VB with {:} vertices",
+                index, vertex_count
+            )));
+        }
+        let next = order.len() as u32;
+        let new_index = *remap.entry(*index).or_insert_with(|| {
+            order.push(*index as usize);
+            next
+        });
+        indices.push(new_index);
+    }
+    let data = order
+        .iter()
+        .flat_map(|vertex| {
+            slots
+                .iter()
+                .flat_map(move |slot| &slot.data[vertex * slot.stride..(vertex + 1) * slot.stride])
+        })
+        .copied()
+        .collect();
+    let ib = IndexBuffer::new(ib.format.clone(), indices)?;
+    Ok((data, ib))
+}
+
+fn import_component(
+    frame_analysis: &Path,
+    target: &Path,
+    ib_hash: &str,
+    character: &str,
+    component_name: &str,
+    texture_names: &[String],
+) -> Result<(Component, Vec<PathBuf>)> {
+    let draws = scan_frame_analysis(frame_analysis)?;
+    // Every part of the model is drawn with the same IB, from its own first index
+    let mut parts: BTreeMap<u32, &DrawCall> = BTreeMap::new();
+    let mut skinning = vec![];
+    for draw in draws.values() {
+        let Some((hash, ib_path)) = &draw.ib else {
+            continue;
+        };
+        if hash != ib_hash {
+            continue;
+        }
+        let txt = fs::read_to_string(ib_path)?;
+        if topology(&txt) == "pointlist" {
+            skinning.push(draw);
+            continue;
+        }
+        let ib = IndexBuffer::parse_txt(&txt)
+            .with_context(|| format!("Failed to Parse {:}", ib_path.display()))?;
+        parts.entry(ib.first).or_insert(draw);
+    }
+    if parts.is_empty() {
+        return Err(Error::msg(format!(
+            "No draw calls use the IB {:} in {:}",
+            ib_hash,
+            frame_analysis.display()
+        )));
+    }
+
+    let mut component = Component {
+        component_name: component_name.to_owned(),
+        object_classifications: vec!["Head".to_owned(), "Body".to_owned(), "Extra".to_owned()],
+        draw_vb: String::new(),
+        position_vb: String::new(),
+        blend_vb: String::new(),
+        texcoord_vb: String::new(),
+        ib: ib_hash.to_owned(),
+        object_indexes: parts.keys().copied().collect(),
+        texture_hashes: Some(vec![]),
+    };
+    let current_name = format!("{:}{:}", character, component_name);
+    let mut textures = vec![];
+    let mut skinned = None;
+    for (index, draw) in parts.values().enumerate() {
+        let current_object = component.object_name(index);
+        info!("Importing {:}{:}", current_name, current_object);
+
+        let mut slots = draw
+            .vbs
+            .iter()
+            .map(|(slot, (hash, path))| read_slot(*slot, hash, path))
+            .collect::<Result<Vec<_>>>()?;
+        let Some(draw_slot) = slots.first() else {
+            return Err(Error::msg(format!(
+                "Draw call for {:}{:} binds no VB",
+                current_name, current_object
+            )));
+        };
+        if index == 0 {
+            component.draw_vb = draw_slot.hash.to_owned();
+            component.position_vb = draw_slot.hash.to_owned();
+            if !slots.iter().any(has_blend) {
+                skinned = match find_skinning_slots(&skinning, vertex_count(draw_slot)) {
+                    Some((position, blend))
+                        if position.stride == POSITION_STRIDE && blend.stride == BLEND_STRIDE =>
+                    {
+                        trace!(
+                            "Found Position VB {:} and Blend VB {:}",
+                            position.hash,
+                            blend.hash
+                        );
+                        component.position_vb = position.hash.to_owned();
+                        component.blend_vb = blend.hash.to_owned();
+                        component.texcoord_vb =
+                            slots.get(1).map(|f| f.hash.to_owned()).unwrap_or_default();
+                        Some((position, blend))
+                    }
+                    Some(_) => {
+                        warn!(
+                            "Skinning VBs of {:} cannot be split. Importing it unskinned",
+                            current_name
+                        );
+                        None
+                    }
+                    None => {
+                        trace!("No Skinning Pass found for {:}", current_name);
+                        None
+                    }
+                };
+            }
+        }
+        // The drawn vb0 holds skinned vertices, so the unskinned Position VB replaces
+        // it. Blend sits between Position and Texcoord, matching how `gen-ini` splits it
+        if let Some((position, blend)) = &skinned {
+            slots[0] = position.clone();
+            slots.insert(1, blend.clone());
+        }
+
+        let (_, ib_path) = draw.ib.as_ref().unwrap();
+        let ib = IndexBuffer::parse_txt(&fs::read_to_string(ib_path)?)?;
+        let fmt = combine_fmt(&slots, &ib);
+        let (vb, ib) = compact_part(&slots, &ib)
+            .with_context(|| format!("Failed to Import {:}{:}", current_name, current_object))?;
+        let part_path = target.join(format!("{:}{:}", current_name, current_object));
+        VertexBuffer::new(fmt.stride, vb)?.write(&part_path.with_extension("vb"))?;
+        ib.write(&part_path.with_extension("ib"))?;
+        fmt.write(&part_path.with_extension("fmt"))?;
+
+        let mut texture_hashes = vec![];
+        for (slot, (hash, path)) in &draw.textures {
+            let Some(name) = texture_names.get(*slot as usize) else {
+                continue;
+            };
+            let extension = format!(".{:}", path.extension().unwrap().to_string_lossy());
+            let texture_path = target.join(format!(
+                "{:}{:}{:}{:}",
+                current_name, current_object, name, extension
+            ));
+            fs::copy(path, &texture_path)?;
+            textures.push(texture_path);
+            texture_hashes.push(TextureHash {
+                name: name.to_owned(),
+                extension,
+                hash: hash.to_owned(),
+            });
+        }
+        component
+            .texture_hashes
+            .as_mut()
+            .unwrap()
+            .push(texture_hashes);
+    }
+    Ok((component, textures))
+}
+
+/// Hashes of every IB drawn in the frame analysis, with how many draw calls use them
+fn list_ib_hashes(frame_analysis: &Path) -> Result<Vec<(String, usize)>> {
+    let draws = scan_frame_analysis(frame_analysis)?;
+    Ok(draws
+        .values()
+        .filter_map(|draw| draw.ib.as_ref().map(|(hash, _)| hash.to_owned()))
+        .counts()
+        .into_iter()
+        .sorted()
+        .collect())
+}
+
+pub fn import(
+    frame_analysis: &Path,
+    ib: Option<String>,
+    component_name: &str,
+    texture_names: &[String],
+) {
+    if !does_config_exist() {
+        error!("Config does not exist. Exiting....");
+        exit(1);
+    }
+    let ib_hash = match ib {
+        Some(ib) => ib.to_lowercase(),
+        None => {
+            let hashes = list_ib_hashes(frame_analysis).expect("Failed to Scan Frame Analysis");
+            hashes
+                .iter()
+                .for_each(|(hash, draws)| info!("IB {:} drawn {:} times", hash, draws));
+            get_input_string_with_validator(
+                "IB Hash to Import",
+                None,
+                move |input: &String| -> Result<(), &str> {
+                    match hashes.iter().any(|(hash, _)| hash == &input.to_lowercase()) {
+                        true => Ok(()),
+                        false => Err("No draw calls use this IB. Enter one of the listed hashes"),
+                    }
+                },
+            )
+            .to_lowercase()
+        }
+    };
+
+    let target = PathBuf::from("./Source/Model");
+    fs::create_dir_all(&target).expect("Failed to Create Source/Model");
+    let character = CONFIG.lock().unwrap().project_name.replace(' ', "");
+    let (component, textures) = import_component(
+        frame_analysis,
+        &target,
+        &ib_hash,
+        &character,
+        component_name,
+        texture_names,
+    )
+    .expect("Failed to Import Model");
+
+    let mut components = match target.join("hash.json").exists() {
+        true => load_components(&target).expect("Failed to Load hash.json"),
+        false => vec![],
+    };
+    components.retain(|f| f.component_name != component.component_name);
+    components.push(component);
+    fs::write(
+        target.join("hash.json"),
+        serde_json::to_string_pretty(&components).expect("Failed to Serialize hash.json"),
+    )
+    .expect("Failed to Write hash.json");
+
+    let split_rules = CONFIG.lock().unwrap().split_rules.clone();
+    let tex_units = textures
+        .par_iter()
+        .map(|f| {
+            (
+                f.file_stem().unwrap().to_str().unwrap().to_string(),
+                generate_tex_split(
+                    f.to_path_buf(),
+                    &PathBuf::from("."),
+                    &PathBuf::from("./Textures/Model"),
+                    &split_rules,
+                )
+                .expect("Failed to Generate Texture Unit"),
+            )
+        })
+        .collect::<HashMap<_, _>>();
+    CONFIG.lock().unwrap().model_textures.extend(tex_units);
+    CONFIG.lock().unwrap().save_project_conf(None);
+    info!("Imported IB {:} into {:}", ib_hash, target.display());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a `vb*.txt` dump listing the whole input layout, as 3DMigoto does
+    fn write_vb(
+        dir: &Path,
+        file: &str,
+        slot: u32,
+        stride: usize,
+        elements: &[(&str, &str, u32, usize)],
+        vertices: usize,
+    ) {
+        let mut txt = format!(
+            "stride: {:}\nfirst vertex: 0\nvertex count: {:}\ntopology: trianglelist\n",
+            stride, vertices
+        );
+        for (index, (name, format, input_slot, offset)) in elements.iter().enumerate() {
+            txt += &format!(
+                "element[{:}]:\n  SemanticName: {:}\n  SemanticIndex: 0\n  Format: {:}\n  InputSlot: {:}\n  AlignedByteOffset: {:}\n  InputSlotClass: per-vertex\n  InstanceDataStepRate: 0\n",
+                index, name, format, input_slot, offset
+            );
+        }
+        txt += "\nvertex-data:\n\n";
+        for vertex in 0..vertices {
+            for (name, format, input_slot, offset) in elements.iter().filter(|f| f.2 == slot) {
+                let components = format.matches("32").count();
+                let values = vec![vertex.to_string(); components].join(", ");
+                txt += &format!(
+                    "vb{:}[{:}]+{:03} {:}: {:}\n",
+                    input_slot, vertex, offset, name, values
+                );
+            }
+            txt += "\n";
+        }
+        fs::write(dir.join(file), txt).unwrap();
+    }
+
+    fn write_ib(dir: &Path, file: &str, first: u32, topology: &str, indices: &str) {
+        fs::write(
+            dir.join(file),
+            format!(
+                "byte offset: 0\nfirst index: {:}\nindex count: 3\ntopology: {:}\nformat: DXGI_FORMAT_R16_UINT\n\n{:}\n",
+                first, topology, indices
+            ),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    pub fn test_import_skinned_component() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let (dump, target) = (root.join("FrameAnalysis"), root.join("Model"));
+        fs::create_dir_all(&dump).unwrap();
+        fs::create_dir_all(&target).unwrap();
+
+        // The skinning pass binds the IB with the unskinned Position and Blend VBs
+        let skinning = [
+            ("POSITION", "R32G32B32_FLOAT", 0, 0),
+            ("NORMAL", "R32G32B32_FLOAT", 0, 12),
+            ("TANGENT", "R32G32B32A32_FLOAT", 0, 24),
+            ("BLENDWEIGHTS", "R32G32B32A32_FLOAT", 1, 0),
+            ("BLENDINDICES", "R32G32B32A32_SINT", 1, 16),
+        ];
+        write_ib(
+            &dump,
+            "000010-ib=aa000001-vs=1111111111111111-ps=2222222222222222.txt",
+            0,
+            "pointlist",
+            "0 1 2",
+        );
+        write_vb(
+            &dump,
+            "000010-vb0=bb000001-vs=1111111111111111-ps=2222222222222222.txt",
+            0,
+            40,
+            &skinning,
+            6,
+        );
+        write_vb(
+            &dump,
+            "000010-vb1=cc000001-vs=1111111111111111-ps=2222222222222222.txt",
+            1,
+            32,
+            &skinning,
+            6,
+        );
+
+        // Each part is then drawn from the skinned vb0, with its own first index
+        let draw = [
+            ("POSITION", "R32G32B32_FLOAT", 0, 0),
+            ("NORMAL", "R32G32B32_FLOAT", 0, 12),
+            ("TANGENT", "R32G32B32A32_FLOAT", 0, 24),
+            ("TEXCOORD", "R32G32_FLOAT", 1, 0),
+        ];
+        for (draw_call, first, indices) in [("000020", 0, "0 1 2"), ("000030", 3, "3 4 5")] {
+            let prefix = format!("{:}-", draw_call);
+            let suffix = "-vs=3333333333333333-ps=4444444444444444.txt";
+            for (slot, extension) in [(0, "dds"), (1, "jpg")] {
+                fs::write(
+                    dump.join(format!(
+                        "{:}ps-t{:}=ff00000{:}{:}",
+                        prefix,
+                        slot,
+                        slot,
+                        suffix.replace("txt", extension)
+                    )),
+                    "",
+                )
+                .unwrap();
+            }
+            write_ib(
+                &dump,
+                &format!("{:}ib=aa000001{:}", prefix, suffix),
+                first,
+                "trianglelist",
+                indices,
+            );
+            write_vb(
+                &dump,
+                &format!("{:}vb0=dd000001{:}", prefix, suffix),
+                0,
+                40,
+                &draw,
+                6,
+            );
+            write_vb(
+                &dump,
+                &format!("{:}vb1=ee000001{:}", prefix, suffix),
+                1,
+                8,
+                &draw,
+                6,
+            );
+        }
+
+        let texture_names = ["Diffuse".to_owned(), "LightMap".to_owned()];
+        let (component, textures) =
+            import_component(&dump, &target, "aa000001", "Furina", "Body", &texture_names).unwrap();
+        assert_eq!(component.draw_vb, "dd000001");
+        assert_eq!(component.position_vb, "bb000001");
+        assert_eq!(component.blend_vb, "cc000001");
+        assert_eq!(component.texcoord_vb, "ee000001");
+        assert_eq!(component.object_indexes, vec![0, 3]);
+        // Only the dds is imported, the jpg preview is skipped
+        assert_eq!(
+            textures,
+            vec![
+                target.join("FurinaBodyHeadDiffuse.dds"),
+                target.join("FurinaBodyBodyDiffuse.dds")
+            ]
+        );
+        let texture_hashes = component.texture_hashes.as_ref().unwrap();
+        assert_eq!(texture_hashes[0].len(), 1);
+        assert_eq!(texture_hashes[0][0].extension, ".dds");
+
+        // Parts are written as Position, Blend then Texcoord
+        let fmt = Fmt::read(&target.join("FurinaBodyHead.fmt")).unwrap();
+        assert_eq!(fmt.stride, POSITION_STRIDE + BLEND_STRIDE + 8);
+        assert_eq!(fmt.elements[3].semantic_name, "BLENDWEIGHTS");
+        let vb = VertexBuffer::read(&target.join("FurinaBodyBody.vb"), fmt.stride).unwrap();
+        assert_eq!(vb.vertex_count(), 3);
+    }
+}
@@ -1,27 +1,39 @@
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use itertools::Itertools;
-use log::info;
-use std::path::PathBuf;
+use log::{error, info, warn};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    process::exit,
+};
 
 use crate::{
-    utils::ini::{Ini, IniSegment},
+    utils::ini::{Ini, IniSegment, SectionKind},
     CONFIG,
 };
 
-use super::config::ProjectType;
+use super::{
+    config::{ProjectType, ShaderUnit},
+    manifest,
+    model::assemble_model,
+    variants::apply_variants,
+    verify::check_output,
+};
 
-fn gen_tex_ini() -> Result<()> {
+fn gen_tex_ini(products: &mut Vec<String>, variant_inis: &mut HashMap<String, String>) -> Result<HashSet<String>> {
     let texture_units = CONFIG.lock().unwrap().texture_textures.to_owned();
     let mod_path = PathBuf::from("./Output");
     let project_name = CONFIG.lock().unwrap().project_name.to_owned();
-    let ini_path = mod_path.join(format!("{:}_Texture.ini", project_name));
+    let ini_file = format!("{:}_Texture.ini", project_name);
+    let ini_path = mod_path.join(&ini_file);
 
     let mut ini = Ini::new(
         project_name.as_str(),
-        CONFIG.lock().unwrap().authors.iter().join(", "),
+        CONFIG.lock().unwrap().authors.iter().sorted().join(", "),
     );
 
-    for (hash, _) in texture_units {
+    for hash in texture_units.keys().sorted() {
         ini = ini
             .add_override(
                 IniSegment::new(format!("TextureOverride{:}", hash).as_str())
@@ -33,19 +45,207 @@ fn gen_tex_ini() -> Result<()> {
                     .add_field("filename", format!("{:}.dds", hash).as_str()),
             );
     }
+    let (ini, matched) = apply_variants(ini, &CONFIG.lock().unwrap().variants, &ini_file, variant_inis)?;
     ini.export(&ini_path)?;
+    products.push(ini_file);
     info!("Texture Ini Built at: {:#?}", &ini_path);
-    Ok(())
+    Ok(matched)
+}
+
+fn gen_model_ini(products: &mut Vec<String>, variant_inis: &mut HashMap<String, String>) -> Result<HashSet<String>> {
+    let mod_path = PathBuf::from("./Output");
+    let project_name = CONFIG.lock().unwrap().project_name.to_owned();
+    let ini_file = format!("{:}_Model.ini", project_name);
+    let ini_path = mod_path.join(&ini_file);
+
+    let ini = Ini::new(
+        project_name.as_str(),
+        CONFIG.lock().unwrap().authors.iter().sorted().join(", "),
+    );
+    let ini = assemble_model(&PathBuf::from("./Source/Model"), &mod_path, ini, products)?;
+    let (ini, matched) = apply_variants(ini, &CONFIG.lock().unwrap().variants, &ini_file, variant_inis)?;
+    ini.export(&ini_path)?;
+    products.push(ini_file);
+    info!("Model Ini Built at: {:#?}", &ini_path);
+    Ok(matched)
+}
+
+/// Copies `source` into Output by its file name. `copied` holds the source of every
+/// file copied so far, as two different files with the same name would overwrite
+/// each other and be loaded by every section using either
+fn copy_to_output(
+    source: &Path,
+    mod_path: &Path,
+    copied: &mut HashMap<String, PathBuf>,
+    products: &mut Vec<String>,
+) -> Result<String> {
+    let file_name = source
+        .file_name()
+        .with_context(|| format!("Invalid Path {:}", source.display()))?
+        .to_string_lossy()
+        .to_string();
+    match copied.get(&file_name) {
+        Some(other) if other != source => bail!(
+            "{:} and {:} would both be Copied to Output as {:}. Rename one of them",
+            other.display(),
+            source.display(),
+            file_name
+        ),
+        Some(_) => return Ok(file_name),
+        None => (),
+    }
+    copied.insert(file_name.to_owned(), source.to_path_buf());
+    fs::copy(source, mod_path.join(&file_name))
+        .with_context(|| format!("Failed to Copy {:}", source.display()))?;
+    products.push(file_name.to_owned());
+    Ok(file_name)
+}
+
+/// Adds a ShaderOverride running a CustomShader with the replaced shader for every
+/// shader unit, copying the shaders and their resources into `mod_path`
+fn add_shaders(
+    mut ini: Ini,
+    shader_units: &HashMap<String, ShaderUnit>,
+    mod_path: &Path,
+    products: &mut Vec<String>,
+) -> Result<Ini> {
+    let mut copied = HashMap::new();
+    for (hash, unit) in shader_units.iter().sorted_by_key(|f| f.0) {
+        let custom_shader = format!("CustomShader{:}", hash);
+        let shader_file = copy_to_output(&unit.path, mod_path, &mut copied, products)?;
+        let mut shader = IniSegment::new(&custom_shader).add_field(&unit.stage.to_string(), &shader_file);
+        for (slot, resource) in &unit.resources {
+            let resource_name = format!("Resource{:}{:}", hash, slot);
+            shader = shader.add_field(&format!("{:}-t{:}", unit.stage, slot), &resource_name);
+            ini = ini.add_resource(
+                IniSegment::new(&resource_name)
+                    .add_field("filename", &copy_to_output(resource, mod_path, &mut copied, products)?),
+            );
+        }
+        // Redraws with the replaced shader, skipping the original draw
+        ini = ini
+            .add_override(
+                IniSegment::new(&format!("ShaderOverride{:}", hash))
+                    .add_field("hash", hash)
+                    .add_field("allow_duplicate_hash", "overrule")
+                    .add_field("handling", "skip")
+                    .add_field("run", &custom_shader),
+            )
+            .add_section(SectionKind::CommandList, shader.add_field("draw", "from_caller"));
+    }
+    Ok(ini)
+}
+
+fn gen_shader_ini(products: &mut Vec<String>, variant_inis: &mut HashMap<String, String>) -> Result<HashSet<String>> {
+    let shader_units = CONFIG.lock().unwrap().shaders.to_owned();
+    let mod_path = PathBuf::from("./Output");
+    let project_name = CONFIG.lock().unwrap().project_name.to_owned();
+    let ini_file = format!("{:}_Shader.ini", project_name);
+    let ini_path = mod_path.join(&ini_file);
+    fs::create_dir_all(&mod_path)?;
+
+    let ini = Ini::new(
+        project_name.as_str(),
+        CONFIG.lock().unwrap().authors.iter().sorted().join(", "),
+    );
+    let ini = add_shaders(ini, &shader_units, &mod_path, products)?;
+    let (ini, matched) = apply_variants(ini, &CONFIG.lock().unwrap().variants, &ini_file, variant_inis)?;
+    ini.export(&ini_path)?;
+    products.push(ini_file);
+    info!("Shader Ini Built at: {:#?}", &ini_path);
+    Ok(matched)
 }
 
 pub fn gen_ini() {
     let project_types = CONFIG.lock().unwrap().project_type.to_owned();
+    let mut matched = HashSet::new();
+    let mut products = vec![];
+    let mut variant_inis = HashMap::new();
     for project_type in project_types {
-        match project_type {
-            // INFO: Should be Implemented within gRPC Module;
-            ProjectType::Model => (),
-            ProjectType::Texture => gen_tex_ini().expect("Generate Ini"),
-            ProjectType::Shader => (),
-        }
+        matched.extend(match project_type {
+            ProjectType::Model => gen_model_ini(&mut products, &mut variant_inis).expect("Generate Model Ini"),
+            ProjectType::Texture => gen_tex_ini(&mut products, &mut variant_inis).expect("Generate Ini"),
+            ProjectType::Shader => gen_shader_ini(&mut products, &mut variant_inis).expect("Generate Shader Ini"),
+        });
+    }
+    if let Err(err) = manifest::record("gen-ini", products, true) {
+        error!("{:#}", err);
+    }
+    let variants = CONFIG.lock().unwrap().variants.to_owned();
+    for (variant_name, variant) in variants.iter().sorted_by_key(|f| f.0) {
+        variant
+            .options
+            .iter()
+            .flat_map(|f| f.textures.keys().chain(&f.parts))
+            .unique()
+            .filter(|f| !matched.contains(*f))
+            .for_each(|f| warn!("Variant {:} swaps {:}, which is not in any generated Ini", variant_name, f));
+    }
+    if !check_output(&manifest::shipped(Path::new("./Output"))) {
+        error!("Generated Mod Failed Verification");
+        exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_add_shaders() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (source, output) = (tmp.path().join("Source"), tmp.path().join("Output"));
+        fs::create_dir_all(source.join("Textures")).unwrap();
+        fs::create_dir_all(source.join("Other")).unwrap();
+        fs::create_dir_all(&output).unwrap();
+        fs::write(source.join("0123456789abcdef-ps_replace.txt"), "ps").unwrap();
+        fs::write(source.join("fedcba9876543210-vs_replace.txt"), "vs").unwrap();
+        fs::write(source.join("Textures/Ramp.dds"), "ramp").unwrap();
+        fs::write(source.join("Other/Ramp.dds"), "other").unwrap();
+        let shader_units = |vs_ramp: &str| -> HashMap<String, ShaderUnit> {
+            serde_yaml::from_str(&format!(
+                "0123456789abcdef: {{ stage: ps, path: {:?}, resources: {{ 7: {:?} }} }}
+fedcba9876543210: {{ stage: vs, path: {:?}, resources: {{ 0: {:?} }} }}",
+                source.join("0123456789abcdef-ps_replace.txt"),
+                source.join("Textures/Ramp.dds"),
+                source.join("fedcba9876543210-vs_replace.txt"),
+                source.join(vs_ramp),
+            ))
+            .unwrap()
+        };
+
+        // Both shaders may bind the same file, which is copied once
+        let mut products = vec![];
+        let ini = Ini::new("Mod", "A".to_owned());
+        let ini = add_shaders(ini, &shader_units("Textures/Ramp.dds"), &output, &mut products)
+            .unwrap()
+            .to_string();
+        assert!(ini.contains(
+            "[ShaderOverride0123456789abcdef]\nhash=0123456789abcdef\nallow_duplicate_hash=overrule\n\
+             handling=skip\nrun=CustomShader0123456789abcdef\n"
+        ));
+        assert!(ini.contains(
+            "[CustomShader0123456789abcdef]\nps=0123456789abcdef-ps_replace.txt\n\
+             ps-t7=Resource0123456789abcdef7\ndraw=from_caller\n"
+        ));
+        assert!(ini.contains(
+            "[CustomShaderfedcba9876543210]\nvs=fedcba9876543210-vs_replace.txt\n\
+             vs-t0=Resourcefedcba98765432100\ndraw=from_caller\n"
+        ));
+        assert!(ini.contains("[Resource0123456789abcdef7]\nfilename=Ramp.dds\n"));
+        assert!(ini.contains("[Resourcefedcba98765432100]\nfilename=Ramp.dds\n"));
+        assert_eq!(
+            products,
+            vec!["0123456789abcdef-ps_replace.txt", "Ramp.dds", "fedcba9876543210-vs_replace.txt"]
+        );
+        assert_eq!(fs::read_to_string(output.join("Ramp.dds")).unwrap(), "ramp");
+
+        // A different file with the same name would overwrite it
+        let ini = Ini::new("Mod", "A".to_owned());
+        let err = add_shaders(ini, &shader_units("Other/Ramp.dds"), &output, &mut vec![])
+            .err()
+            .unwrap();
+        assert!(err.to_string().ends_with("would both be Copied to Output as Ramp.dds. Rename one of them"));
+        assert_eq!(fs::read_to_string(output.join("Ramp.dds")).unwrap(), "ramp");
     }
 }
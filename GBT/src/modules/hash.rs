@@ -0,0 +1,445 @@
+use log::{trace, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ComponentError {
+    /// Neither `hash.json` nor the legacy `hash_info.json` could be found
+    ///
+    /// `(dump path)`
+    #[error("{0:?}: Cannot find hash.json (or legacy hash_info.json)")]
+    NotFound(PathBuf),
+
+    /// Failed to read or parse the hash file
+    ///
+    /// `(path, error message)`
+    #[error("{0:?}: {1}")]
+    Parse(PathBuf, String),
+
+    /// A field in the hash file holds an invalid value
+    ///
+    /// `(path, field, error message)`
+    #[error("{0:?}: {1}: {2}")]
+    Invalid(PathBuf, String, String),
+
+    /// A file the hash file refers to is missing from the dump
+    ///
+    /// `(path, field, missing file)`
+    #[error("{0:?}: {1}: Missing file {2:?}")]
+    MissingFile(PathBuf, String, PathBuf),
+}
+
+/// A single `[name, extension, hash]` entry of `texture_hashes`
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(from = "(String, String, String)", into = "(String, String, String)")]
+pub struct TextureHash {
+    pub name: String,
+    pub extension: String,
+    pub hash: String,
+}
+
+impl From<(String, String, String)> for TextureHash {
+    fn from((name, extension, hash): (String, String, String)) -> Self {
+        Self {
+            name,
+            extension,
+            hash,
+        }
+    }
+}
+
+impl From<TextureHash> for (String, String, String) {
+    fn from(value: TextureHash) -> Self {
+        (value.name, value.extension, value.hash)
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Component {
+    // Custom names were added later, so older dumps have none
+    #[serde(default)]
+    pub component_name: String,
+    // Older dumps used Extra as the third object
+    #[serde(default = "default_object_classifications")]
+    pub object_classifications: Vec<String>,
+    #[serde(default)]
+    pub draw_vb: String,
+    #[serde(default)]
+    pub position_vb: String,
+    #[serde(default)]
+    pub blend_vb: String,
+    #[serde(default)]
+    pub texcoord_vb: String,
+    #[serde(default)]
+    pub ib: String,
+    #[serde(default)]
+    pub object_indexes: Vec<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub texture_hashes: Option<Vec<Vec<TextureHash>>>,
+}
+
+fn default_object_classifications() -> Vec<String> {
+    vec!["Head".to_owned(), "Body".to_owned(), "Extra".to_owned()]
+}
+
+pub fn is_hash(value: &str) -> bool {
+    value.len() == 8 && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+impl Component {
+    /// Components without a draw vb only override textures
+    pub fn has_draw(&self) -> bool {
+        !self.draw_vb.is_empty()
+    }
+
+    /// Components without a blend vb (weapons, objects, etc.) keep their vb unsplit
+    pub fn has_blend(&self) -> bool {
+        !self.blend_vb.is_empty()
+    }
+
+    pub fn object_name(&self, index: usize) -> String {
+        let classifications = &self.object_classifications;
+        if index + 1 > classifications.len() {
+            format!(
+                "{:}{:}",
+                classifications.last().unwrap(),
+                index + 2 - classifications.len()
+            )
+        } else {
+            classifications[index].to_owned()
+        }
+    }
+
+    /// Faces are drawn through their texture hashes and only get their Diffuse replaced
+    pub fn is_face(&self) -> bool {
+        self.component_name == "Face"
+    }
+
+    pub fn texture_hashes(&self, index: usize) -> Vec<TextureHash> {
+        let mut textures = match self.texture_hashes.as_ref().and_then(|f| f.get(index)) {
+            Some(textures) => textures.to_owned(),
+            // Older dumps only shipped Diffuse and LightMap
+            None => ["Diffuse", "LightMap"]
+                .iter()
+                .map(|name| TextureHash {
+                    name: name.to_string(),
+                    extension: ".dds".to_owned(),
+                    hash: "_".to_owned(),
+                })
+                .collect(),
+        };
+        if self.is_face() {
+            textures.truncate(1);
+        }
+        textures
+    }
+
+    fn validate(&self, hash_path: &Path, index: usize) -> Vec<ComponentError> {
+        let mut errors = vec![];
+        let mut invalid = |field: &str, message: String| {
+            errors.push(ComponentError::Invalid(
+                hash_path.to_path_buf(),
+                format!("[{:}].{:}", index, field),
+                message,
+            ))
+        };
+
+        if self.object_classifications.is_empty() {
+            invalid("object_classifications", "Must not be empty".to_owned());
+        }
+        if self.object_indexes.is_empty() {
+            invalid("object_indexes", "Must not be empty".to_owned());
+        }
+        if !self.object_indexes.windows(2).all(|f| f[0] < f[1]) {
+            invalid(
+                "object_indexes",
+                format!("Must be strictly increasing, found {:?}", self.object_indexes),
+            );
+        }
+
+        if self.has_draw() {
+            let mut hashes = vec![
+                ("draw_vb", &self.draw_vb),
+                ("position_vb", &self.position_vb),
+                ("ib", &self.ib),
+            ];
+            if self.has_blend() {
+                hashes.extend([("blend_vb", &self.blend_vb), ("texcoord_vb", &self.texcoord_vb)]);
+            }
+            for (field, value) in hashes {
+                if !is_hash(value) {
+                    invalid(field, format!("{:?} is not a valid hash", value));
+                }
+            }
+        }
+
+        if let Some(texture_hashes) = &self.texture_hashes {
+            if texture_hashes.len() < self.object_indexes.len() {
+                invalid(
+                    "texture_hashes",
+                    format!(
+                        "Has {:} entries but object_indexes has {:}",
+                        texture_hashes.len(),
+                        self.object_indexes.len()
+                    ),
+                );
+            }
+            for (object, textures) in texture_hashes.iter().enumerate() {
+                for (slot, texture) in textures.iter().enumerate() {
+                    let field = format!("texture_hashes[{:}][{:}]", object, slot);
+                    if texture.name.is_empty() {
+                        invalid(&field, "Texture name must not be empty".to_owned());
+                    }
+                    if !texture.extension.starts_with('.') {
+                        invalid(
+                            &field,
+                            format!("{:?} is not a file extension", texture.extension),
+                        );
+                    }
+                    if texture.hash != "_" && !is_hash(&texture.hash) {
+                        invalid(&field, format!("{:?} is not a valid hash", texture.hash));
+                    }
+                }
+            }
+        }
+
+        // Texture only parts and Faces are matched by their texture hashes, so the
+        // "_" placeholder would end up as the hash of their overrides
+        if !self.has_draw() || self.is_face() {
+            for object in 0..self.object_indexes.len() {
+                for (slot, texture) in self.texture_hashes(object).iter().enumerate() {
+                    if texture.hash == "_" {
+                        invalid(
+                            &format!("texture_hashes[{:}][{:}]", object, slot),
+                            format!("{:} needs a hash, as it is matched by it", texture.name),
+                        );
+                    }
+                }
+            }
+        }
+        errors
+    }
+}
+
+/// Reads the components of the dump at `source`, falling back to the legacy
+/// `hash_info.json` next to the dump when it has no `hash.json`
+pub fn load_components(source: &Path) -> Result<Vec<Component>, ComponentError> {
+    let hash_path = source.join("hash.json");
+    if hash_path.exists() {
+        trace!("Loading Components from {:?}", hash_path);
+        let file = File::open(&hash_path)
+            .map_err(|e| ComponentError::Parse(hash_path.clone(), e.to_string()))?;
+        return serde_json::from_reader(file)
+            .map_err(|e| ComponentError::Parse(hash_path.clone(), e.to_string()));
+    }
+
+    let legacy_path = match source.parent() {
+        Some(parent) if parent.join("hash_info.json").exists() => parent.join("hash_info.json"),
+        _ => return Err(ComponentError::NotFound(source.to_path_buf())),
+    };
+    warn!(
+        "Could not find hash.json in {:?}. Falling back to {:?}",
+        source, legacy_path
+    );
+    let file = File::open(&legacy_path)
+        .map_err(|e| ComponentError::Parse(legacy_path.clone(), e.to_string()))?;
+    let mut hash_info: HashMap<String, Component> = serde_json::from_reader(file)
+        .map_err(|e| ComponentError::Parse(legacy_path.clone(), e.to_string()))?;
+    // hash_info.json holds every character, keyed by the name the dump was exported under
+    let files = dump_files(source);
+    // The longest name wins, so `RaidenShogun` is not loaded as `Raiden`
+    let name = hash_info
+        .keys()
+        .filter(|name| files.iter().any(|f| f.starts_with(name.as_str())))
+        .max_by_key(|name| (name.len(), name.as_str()))
+        .cloned()
+        .ok_or_else(|| {
+            ComponentError::Invalid(
+                legacy_path.clone(),
+                "<root>".to_owned(),
+                format!("No entry matches the files in {:?}", source),
+            )
+        })?;
+    Ok(vec![hash_info.remove(&name).unwrap()])
+}
+
+fn dump_files(source: &Path) -> Vec<String> {
+    fs::read_dir(source)
+        .map(|dir| {
+            dir.filter_map(|f| f.ok())
+                .map(|f| f.file_name().to_string_lossy().to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Resolves the name the dump was exported under (e.g. `Furina` for `FurinaHead.fmt`)
+pub fn character_name(source: &Path, components: &[Component]) -> Option<String> {
+    let files = dump_files(source);
+    components
+        .iter()
+        .filter(|component| component.has_draw())
+        .find_map(|component| {
+            let suffix = format!(
+                "{:}{:}.fmt",
+                component.component_name,
+                component.object_classifications.first()?
+            );
+            files
+                .iter()
+                .find_map(|f| f.strip_suffix(suffix.as_str()).map(|f| f.to_owned()))
+        })
+}
+
+/// Checks the components against the schema and against the files in the dump
+pub fn validate_components(source: &Path, components: &[Component]) -> Vec<ComponentError> {
+    let hash_path = source.join("hash.json");
+    let mut errors = components
+        .iter()
+        .enumerate()
+        .flat_map(|(index, component)| component.validate(&hash_path, index))
+        .collect::<Vec<_>>();
+    if !errors.is_empty() {
+        return errors;
+    }
+
+    let character = match character_name(source, components) {
+        Some(character) => character,
+        None if components.iter().any(|f| f.has_draw()) => {
+            errors.push(ComponentError::Invalid(
+                hash_path,
+                "<root>".to_owned(),
+                "No .fmt in the dump matches any Component".to_owned(),
+            ));
+            return errors;
+        }
+        None => return errors,
+    };
+
+    let mut require = |field: String, file: String| {
+        let path = source.join(file);
+        if !path.exists() {
+            errors.push(ComponentError::MissingFile(hash_path.clone(), field, path));
+        }
+    };
+    for (index, component) in components.iter().enumerate() {
+        let current_name = format!("{:}{:}", character, component.component_name);
+        for object in 0..component.object_indexes.len() {
+            let current_object = component.object_name(object);
+            if component.has_draw() {
+                let field = format!("[{:}].object_indexes[{:}]", index, object);
+                for ext in ["vb", "ib"] {
+                    require(
+                        field.clone(),
+                        format!("{:}{:}.{:}", current_name, current_object, ext),
+                    );
+                }
+                if object == 0 {
+                    require(field, format!("{:}{:}.fmt", current_name, current_object));
+                }
+            }
+            for (slot, texture) in component.texture_hashes(object).iter().enumerate() {
+                require(
+                    format!("[{:}].texture_hashes[{:}][{:}]", index, object, slot),
+                    format!(
+                        "{:}{:}{:}{:}",
+                        current_name, current_object, texture.name, texture.extension
+                    ),
+                );
+            }
+        }
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_legacy_defaults() {
+        let components: Vec<Component> = serde_json::from_str(
+            r#"[{"draw_vb": "0a1b2c3d", "position_vb": "1a1b2c3d", "blend_vb": "",
+                "texcoord_vb": "", "ib": "2a1b2c3d", "object_indexes": [0, 120, 300, 400]}]"#,
+        )
+        .unwrap();
+        let component = &components[0];
+
+        assert_eq!(component.component_name, "");
+        assert_eq!(component.object_classifications, default_object_classifications());
+        assert_eq!(component.object_name(2), "Extra");
+        assert_eq!(component.object_name(3), "Extra2");
+        assert_eq!(component.texture_hashes(0)[1].name, "LightMap");
+        assert!(component.validate(Path::new("hash.json"), 0).is_empty());
+    }
+
+    #[test]
+    pub fn test_invalid_fields() {
+        let components: Vec<Component> = serde_json::from_str(
+            r#"[{"component_name": "Hair", "draw_vb": "0a1b2c3d", "position_vb": "xyz",
+                "blend_vb": "3a1b2c3d", "texcoord_vb": "4a1b2c3d", "ib": "2a1b2c3d",
+                "object_indexes": [0, 120], "object_classifications": ["Head", "Body"],
+                "texture_hashes": [[["Diffuse", ".dds", "5a1b2c3d"]]]}]"#,
+        )
+        .unwrap();
+        let errors = components[0]
+            .validate(Path::new("hash.json"), 0)
+            .iter()
+            .map(|f| f.to_string())
+            .collect::<Vec<_>>();
+
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].contains("[0].position_vb"));
+        assert!(errors[1].contains("[0].texture_hashes"));
+    }
+
+    #[test]
+    pub fn test_placeholder_texture_hash() {
+        let components: Vec<Component> = serde_json::from_str(
+            r#"[{"component_name": "Face", "draw_vb": "0a1b2c3d", "position_vb": "1a1b2c3d",
+                "blend_vb": "", "texcoord_vb": "", "ib": "2a1b2c3d", "object_indexes": [0],
+                "texture_hashes": [[["Diffuse", ".dds", "_"]]]}]"#,
+        )
+        .unwrap();
+        let errors = components[0].validate(Path::new("hash.json"), 0);
+
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].to_string().contains("[0].texture_hashes[0][0]"));
+    }
+
+    #[test]
+    pub fn test_legacy_hash_info() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dump = tmp.path().join("RaidenShogun");
+        fs::create_dir_all(&dump).unwrap();
+        fs::write(dump.join("RaidenShogunBody.fmt"), "").unwrap();
+        let component = |draw_vb: &str| {
+            format!(
+                r#"{{"draw_vb": "{:}", "position_vb": "", "blend_vb": "", "texcoord_vb": "",
+                    "ib": "", "object_indexes": [0]}}"#,
+                draw_vb
+            )
+        };
+        fs::write(
+            tmp.path().join("hash_info.json"),
+            format!(
+                r#"{{"Raiden": {:}, "RaidenShogun": {:}, "Rai": {:}}}"#,
+                component("0a1b2c3d"),
+                component("1a1b2c3d"),
+                component("2a1b2c3d")
+            ),
+        )
+        .unwrap();
+
+        // Every load hashes the keys in a different order
+        for _ in 0..8 {
+            let components = load_components(&dump).unwrap();
+            assert_eq!(components[0].draw_vb, "1a1b2c3d");
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use anyhow::{bail, Result};
+use image::RgbaImage;
+
+use super::{
+    config::{BlendMode, Layer},
+    dds::{open_sized, read_channel},
+};
+
+fn blend(mode: BlendMode, base: f32, layer: f32) -> f32 {
+    match mode {
+        BlendMode::Normal => layer,
+        BlendMode::Multiply => base * layer,
+        BlendMode::Screen => 1.0 - (1.0 - base) * (1.0 - layer),
+        BlendMode::Overlay if base < 0.5 => 2.0 * base * layer,
+        BlendMode::Overlay => 1.0 - 2.0 * (1.0 - base) * (1.0 - layer),
+        BlendMode::Add => (base + layer).min(1.0),
+        BlendMode::Darken => base.min(layer),
+        BlendMode::Lighten => base.max(layer),
+    }
+}
+
+/// Rotates the hue of an RGB colour by `hue` degrees and scales its saturation
+fn adjust(rgb: [f32; 3], hue: f32, saturation: f32) -> [f32; 3] {
+    let [r, g, b] = rgb;
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+    let h = match delta {
+        _ if delta == 0.0 => 0.0,
+        _ if max == r => 60.0 * ((g - b) / delta).rem_euclid(6.0),
+        _ if max == g => 60.0 * ((b - r) / delta + 2.0),
+        _ => 60.0 * ((r - g) / delta + 4.0),
+    };
+    let s = match max > 0.0 {
+        true => (delta / max * saturation).clamp(0.0, 1.0),
+        false => 0.0,
+    };
+    let h = (h + hue).rem_euclid(360.0);
+
+    let c = max * s;
+    let x = c * (1.0 - ((h / 60.0).rem_euclid(2.0) - 1.0).abs());
+    let m = max - c;
+    let (r, g, b) = match (h / 60.0) as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+    [r + m, g + m, b + m]
+}
+
+fn to_unit(value: u8) -> f32 {
+    value as f32 / 255.0
+}
+
+fn from_unit(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+/// Composites the layers in order over the RGB of `image`, keeping its alpha
+pub fn apply_layers(image: &mut RgbaImage, layers: &[Layer], resample: bool) -> Result<()> {
+    let (width, height) = image.dimensions();
+    for layer in layers {
+        let source = open_sized(&layer.path, width, height, resample)?;
+        let mask = match &layer.mask {
+            Some(mask) => {
+                let channel = mask.channels.as_deref().unwrap_or("L");
+                if channel.len() != 1 {
+                    bail!("Mask {:} must read a single Channel", mask.path.display());
+                }
+                Some((open_sized(&mask.path, width, height, resample)?, channel.chars().next().unwrap()))
+            }
+            None => None,
+        };
+        for (x, y, base) in image.enumerate_pixels_mut() {
+            let pixel = source.get_pixel(x, y);
+            let mut coverage = to_unit(pixel[3]) * layer.opacity.clamp(0.0, 1.0);
+            if let Some((mask_image, channel)) = &mask {
+                coverage *= to_unit(read_channel(mask_image.get_pixel(x, y), *channel));
+            }
+            if coverage == 0.0 {
+                continue;
+            }
+            let mut rgb = [to_unit(pixel[0]), to_unit(pixel[1]), to_unit(pixel[2])];
+            if layer.hue.is_some() || layer.saturation.is_some() {
+                rgb = adjust(rgb, layer.hue.unwrap_or(0.0), layer.saturation.unwrap_or(1.0));
+            }
+            for (index, value) in rgb.into_iter().enumerate() {
+                let below = to_unit(base[index]);
+                let blended = blend(layer.blend, below, value);
+                base[index] = from_unit(below + (blended - below) * coverage);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    pub fn test_apply_layers() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        RgbaImage::from_pixel(2, 1, Rgba([255, 0, 0, 255]))
+            .save(dir.join("Decal.png"))
+            .unwrap();
+        RgbaImage::from_fn(2, 1, |x, _| Rgba([x as u8 * 255, 0, 0, 255]))
+            .save(dir.join("Mask.png"))
+            .unwrap();
+
+        let mut image = RgbaImage::from_pixel(2, 1, Rgba([128, 128, 128, 7]));
+        let layers: Vec<Layer> = serde_yaml::from_str(&format!(
+            "- {{ path: {:?}, blend: multiply, mask: {:?} }}\n- {{ path: {:?}, opacity: 0.5, hue: 120 }}",
+            dir.join("Decal.png"),
+            format!("{:}:R", dir.join("Mask.png").display()),
+            dir.join("Decal.png"),
+        ))
+        .unwrap();
+        apply_layers(&mut image, &layers, false).unwrap();
+        // Only the masked pixel is multiplied, then both are blended halfway to green
+        assert_eq!(
+            image.pixels().copied().collect::<Vec<_>>(),
+            vec![Rgba([64, 192, 64, 7]), Rgba([64, 128, 0, 7])]
+        );
+
+        RgbaImage::new(1, 1).save(dir.join("Small.png")).unwrap();
+        let small: Vec<Layer> =
+            serde_yaml::from_str(&format!("- path: {:?}", dir.join("Small.png"))).unwrap();
+        assert!(apply_layers(&mut image, &small, false).is_err());
+        apply_layers(&mut image, &small, true).unwrap();
+    }
+}
@@ -1,10 +1,60 @@
-use std::{process::exit, path::PathBuf};
+use std::{collections::BTreeSet, fs, process::exit, path::{Path, PathBuf}};
+use anyhow::Result;
 use normpath::PathExt;
-use log::{error, warn};
+use log::{error, info, trace, warn};
+use serde::{Deserialize, Serialize};
 
 use crate::{utils::copy::copy, CONFIG};
 
-use super::config::does_config_exist;
+use super::{config::does_config_exist, manifest::{shipped, Manifest}, verify::check_output};
+
+/// Files the last `gbt link` copied into the Mods folder, so the next one only
+/// removes those and leaves anything else the user put there alone
+#[derive(Serialize, Deserialize, Default, Debug)]
+struct LinkState {
+    // Files relative to the linked mod
+    #[serde(default)]
+    files: BTreeSet<String>,
+}
+
+impl LinkState {
+    fn path() -> PathBuf {
+        PathBuf::from("./.gbt/link-state.json")
+    }
+
+    /// Loads the files of the last link, or None if it predates the link state
+    fn load() -> Option<Self> {
+        fs::read_to_string(Self::path())
+            .ok()
+            .and_then(|f| serde_json::from_str(&f).ok())
+    }
+
+    fn save(&self) -> Result<()> {
+        fs::create_dir_all(Self::path().parent().unwrap())?;
+        fs::write(Self::path(), serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Removes the files of the previous link that are no longer shipped, along with
+/// the folders they leave empty
+fn remove_stale(target: &Path, previous: &LinkState, current: &BTreeSet<String>) {
+    for file in previous.files.difference(current) {
+        let path = target.join(file);
+        if !path.is_file() {
+            continue;
+        }
+        match fs::remove_file(&path) {
+            Ok(()) => trace!("Removed {:}", path.display()),
+            Err(err) => warn!("Failed to Remove {:}: {:}", path.display(), err),
+        }
+        for parent in path.ancestors().skip(1).take_while(|f| *f != target) {
+            if fs::remove_dir(parent).is_err() {
+                break;
+            }
+        }
+    }
+}
 
 pub fn link(should_symlink: bool) {
     if !does_config_exist() {
@@ -16,10 +66,51 @@ pub fn link(should_symlink: bool) {
         warn!("Migoto Path Not Exist. Exiting..");
         exit(0);
     }
+    let output = PathBuf::from("./Output");
+    let files = shipped(&output);
+    if !check_output(&files) {
+        error!("Output Failed Verification. Refusing to Link..");
+        exit(1);
+    }
     let project_name = CONFIG.lock().unwrap().project_name.to_owned();
-    copy(
-        &PathBuf::from("./Output").normalize().unwrap().into(),
-        &migoto_path.unwrap().join("Mods").join(project_name),
-        should_symlink,
-    ).expect("Copy Failed");
+    let target = migoto_path.unwrap().join("Mods").join(project_name);
+    if should_symlink {
+        // The whole of Output is linked, so nothing that would not be shipped may be in it
+        let unlisted = Manifest::load()
+            .map(|f| f.unlisted(&output))
+            .unwrap_or_default();
+        if !unlisted.is_empty() {
+            unlisted
+                .iter()
+                .for_each(|f| error!("{:} was not Generated by GBT", f.display()));
+            error!("Remove them with `gbt clean --orphans` or Link without --symlink. Refusing to Link..");
+            exit(1);
+        }
+        copy(&output.normalize().unwrap().into(), &target, should_symlink).expect("Copy Failed");
+        return;
+    }
+
+    let current = files
+        .iter()
+        .map(|f| f.strip_prefix(&output).unwrap().to_string_lossy().replace('\\', "/"))
+        .collect::<BTreeSet<_>>();
+    if let Ok(metadata) = target.symlink_metadata() {
+        if metadata.is_symlink() {
+            // Only the previous link goes, never the Output it points to
+            fs::remove_file(&target)
+                .or_else(|_| fs::remove_dir(&target))
+                .expect("Failed to Remove Previous Link");
+        } else if let Some(previous) = LinkState::load() {
+            remove_stale(&target, &previous, &current);
+        }
+    }
+    for file in files {
+        let file_target = target.join(file.strip_prefix(&output).unwrap());
+        fs::create_dir_all(file_target.parent().unwrap()).expect("Failed to Create Mod Folder");
+        copy(&file, &file_target, false).expect("Copy Failed");
+    }
+    if let Err(err) = (LinkState { files: current }).save() {
+        error!("Failed to Save Link State: {:#}", err);
+    }
+    info!("Linked {:}", target.display());
 }
@@ -0,0 +1,313 @@
+use itertools::Itertools;
+use lazy_static::lazy_static;
+use log::{error, info, trace};
+use regex::Regex;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    process::exit,
+};
+use thiserror::Error;
+
+use crate::{
+    utils::ini::{IniDocument, IniSection},
+    CONFIG,
+};
+
+use super::{config::does_config_exist, manifest::shipped, mods::is_disabled, variants::variant_textures};
+
+lazy_static! {
+    /// Keys binding a resource to a slot
+    static ref BINDING: Regex = Regex::new(r"(?i)^(this|ib|vb\d+|[vhdgpc]s-(t|cb|u)\d+|o\d+|od)$").unwrap();
+    /// Keys of a shader stage pointing to a shader file
+    static ref STAGE: Regex = Regex::new(r"(?i)^[vhdgpc]s$").unwrap();
+    static ref TOKEN: Regex = Regex::new(r"[A-Za-z0-9_.\\/]+").unwrap();
+}
+
+#[derive(Error, Debug)]
+pub enum LintError {
+    /// Failed to read an ini
+    ///
+    /// `(path, error message)`
+    #[error("{0:?}: {1}")]
+    Io(PathBuf, String),
+
+    /// Field pointing to a file that does not exist
+    ///
+    /// `(ini path, line, section, missing file)`
+    #[error("{0:?}:{1}: [{2}]: Missing file {3:?}")]
+    MissingFile(PathBuf, usize, String, PathBuf),
+
+    /// Binding or `run` naming a section that is not defined
+    ///
+    /// `(ini path, line, section, key, undefined section)`
+    #[error("{0:?}:{1}: [{2}]: {3} refers to undefined [{4}]")]
+    Undefined(PathBuf, usize, String, String, String),
+
+    /// Section defined more than once in the same ini
+    ///
+    /// `(ini path, line, section, line of the first definition)`
+    #[error("{0:?}:{1}: [{2}] is already defined on line {3}")]
+    Duplicate(PathBuf, usize, String, usize),
+
+    /// Resource or command list nothing refers to
+    ///
+    /// `(ini path, line, section)`
+    #[error("{0:?}:{1}: [{2}] is never used")]
+    Unused(PathBuf, usize, String),
+
+    /// File in Output that no ini refers to
+    ///
+    /// `(path)`
+    #[error("{0:?} is not referenced by any ini")]
+    Unreferenced(PathBuf),
+
+    /// Texture unit in the Config whose texture no ini refers to
+    ///
+    /// `(texture unit, built file)`
+    #[error("Texture {0} is built to {1:?} but not referenced by any ini")]
+    UnusedTexture(String, String),
+}
+
+/// Sections that are only run when something refers to them
+fn is_referenced_section(name: &str) -> bool {
+    let name = name.to_lowercase();
+    ["resource", "commandlist", "customshader"]
+        .iter()
+        .any(|f| name.starts_with(f))
+}
+
+/// Resource named by a binding, skipping `ref`/`copy` modifiers and slot to slot copies
+fn bound_resource(value: &str) -> Option<&str> {
+    let resource = value.split_whitespace().last()?;
+    resource
+        .to_lowercase()
+        .starts_with("resource")
+        .then_some(resource)
+}
+
+/// Path of a file relative to the ini, as 3DMigoto resolves it
+fn resolve(ini_path: &Path, file: &str) -> PathBuf {
+    ini_path
+        .parent()
+        .unwrap_or(Path::new("."))
+        .join(file.trim().trim_matches('"').replace('\\', "/"))
+}
+
+/// Lints a single ini, adding the files it refers to to `files`
+fn lint_ini(ini_path: &Path, files: &mut HashSet<PathBuf>) -> Vec<LintError> {
+    trace!("Linting {:?}", ini_path);
+    let document = match IniDocument::read(ini_path) {
+        Ok(document) => document,
+        Err(err) => return vec![LintError::Io(ini_path.to_path_buf(), err.to_string())],
+    };
+    let sections = document.sections();
+    let mut errors = vec![];
+
+    let mut defined: HashMap<String, &IniSection> = HashMap::new();
+    for section in &sections {
+        match defined.get(&section.name.to_lowercase()) {
+            Some(first) => errors.push(LintError::Duplicate(
+                ini_path.to_path_buf(),
+                section.line,
+                section.name.to_owned(),
+                first.line,
+            )),
+            None => {
+                defined.insert(section.name.to_lowercase(), section);
+            }
+        }
+    }
+
+    let mut referenced = HashSet::new();
+    for section in &sections {
+        for field in &section.fields {
+            let key = field.key.to_lowercase();
+            referenced.extend(
+                TOKEN
+                    .find_iter(field.value)
+                    .map(|f| f.as_str().to_lowercase()),
+            );
+
+            let file = match key.as_str() {
+                "filename" => Some(field.value),
+                _ if STAGE.is_match(&key) && field.value.contains('.') => Some(field.value),
+                _ => None,
+            };
+            if let Some(file) = file {
+                let path = resolve(ini_path, file);
+                if !path.is_file() {
+                    errors.push(LintError::MissingFile(
+                        ini_path.to_path_buf(),
+                        field.line,
+                        section.name.to_owned(),
+                        path.to_owned(),
+                    ));
+                }
+                files.insert(path);
+                continue;
+            }
+
+            let target = match key.as_str() {
+                "run" if !field.value.to_lowercase().starts_with("builtin") => {
+                    Some(field.value.trim())
+                }
+                _ if BINDING.is_match(&key) => bound_resource(field.value),
+                _ => None,
+            };
+            if let Some(target) = target.filter(|f| !defined.contains_key(&f.to_lowercase())) {
+                errors.push(LintError::Undefined(
+                    ini_path.to_path_buf(),
+                    field.line,
+                    section.name.to_owned(),
+                    field.key.to_owned(),
+                    target.to_owned(),
+                ));
+            }
+        }
+    }
+
+    for section in defined
+        .values()
+        .filter(|f| is_referenced_section(f.name))
+        .filter(|f| !referenced.contains(&f.name.to_lowercase()))
+        .sorted_by_key(|f| f.line)
+    {
+        errors.push(LintError::Unused(
+            ini_path.to_path_buf(),
+            section.line,
+            section.name.to_owned(),
+        ));
+    }
+    errors.sort_by_key(|f| match f {
+        LintError::MissingFile(_, line, ..)
+        | LintError::Undefined(_, line, ..)
+        | LintError::Duplicate(_, line, ..)
+        | LintError::Unused(_, line, ..) => *line,
+        _ => 0,
+    });
+    errors
+}
+
+/// Lints the shipped inis of `output`, checking that the other `shipped` files
+/// and the `textures` the Config builds into it are all referenced
+pub fn lint_output(output: &Path, shipped: &[PathBuf], textures: &[String]) -> Vec<LintError> {
+    let is_ini = |f: &PathBuf| {
+        f.extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("ini"))
+    };
+    let inis = shipped
+        .iter()
+        .filter(|f| is_ini(f) && !is_disabled(f))
+        .sorted()
+        .collect::<Vec<_>>();
+
+    let mut files = HashSet::new();
+    let mut errors = inis
+        .iter()
+        .flat_map(|ini| lint_ini(ini, &mut files))
+        .collect::<Vec<_>>();
+
+    for path in shipped.iter().filter(|f| !is_ini(f)).sorted() {
+        if !files.contains(path) {
+            errors.push(LintError::Unreferenced(path.to_owned()));
+        }
+    }
+    for texture in textures.iter().sorted() {
+        let file_name = format!("{:}.dds", texture);
+        if !files.contains(&output.join(&file_name)) {
+            errors.push(LintError::UnusedTexture(texture.to_owned(), file_name));
+        }
+    }
+    errors
+}
+
+pub fn lint() {
+    if !does_config_exist() {
+        error!("Config does not exist. Exiting....");
+        exit(1);
+    }
+    let config = CONFIG.lock().unwrap().to_owned();
+    let textures = config
+        .model_textures
+        .keys()
+        .chain(config.texture_textures.keys())
+        .cloned()
+        .chain(variant_textures(&config.variants).into_keys())
+        .collect::<Vec<_>>();
+    let output = PathBuf::from("./Output");
+    let errors = lint_output(&output, &shipped(&output), &textures);
+    if !errors.is_empty() {
+        errors.iter().for_each(|err| error!("{err}"));
+        error!("Lint Failed with {:} Problems", errors.len());
+        exit(1);
+    }
+    info!("Lint Passed");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    pub fn test_lint_output() {
+        let tmp = tempfile::tempdir().unwrap();
+        let output = tmp.path();
+        fs::write(output.join("Body.dds"), "").unwrap();
+        fs::write(output.join("Stale.dds"), "").unwrap();
+        fs::write(
+            output.join("Mod.ini"),
+            "[TextureOverrideBody]
+hash = 1a2b3c4d
+this = ResourceBody
+ps-t1 = ResourceFace
+run = CommandListUnused
+
+[ResourceBody]
+filename = Body.dds
+
+[ResourceHead]
+filename = Head.dds
+
+[ResourceBody]
+filename = Body.dds
+",
+        )
+        .unwrap();
+        // Left behind by a renamed project, so neither it nor what it references is shipped
+        fs::write(output.join("Old.ini"), "[ResourceOld]\nfilename = Old.dds\n").unwrap();
+        fs::write(output.join("Old.dds"), "").unwrap();
+        let shipped = ["Body.dds", "Stale.dds", "Mod.ini"].map(|f| output.join(f));
+        let errors = lint_output(output, &shipped, &["Body".to_owned(), "Hair".to_owned()])
+            .iter()
+            .map(|f| f.to_string())
+            .collect::<Vec<_>>();
+        let ini = output.join("Mod.ini");
+        assert_eq!(
+            errors,
+            vec![
+                format!(
+                    "{:?}:4: [TextureOverrideBody]: ps-t1 refers to undefined [ResourceFace]",
+                    ini
+                ),
+                format!(
+                    "{:?}:5: [TextureOverrideBody]: run refers to undefined [CommandListUnused]",
+                    ini
+                ),
+                format!("{:?}:10: [ResourceHead] is never used", ini),
+                format!(
+                    "{:?}:11: [ResourceHead]: Missing file {:?}",
+                    ini,
+                    output.join("Head.dds")
+                ),
+                format!("{:?}:13: [ResourceBody] is already defined on line 7", ini),
+                format!(
+                    "{:?} is not referenced by any ini",
+                    output.join("Stale.dds")
+                ),
+                "Texture Hair is built to \"Hair.dds\" but not referenced by any ini".to_owned(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use anyhow::{Context, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+};
+use walkdir::WalkDir;
+
+/// Files each generating step wrote to Output. Only these are linked and exported,
+/// so files left behind by renamed or removed units never reach players
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct Manifest {
+    // Key -> Step that generated the files (e.g. build, gen-ini)
+    // Value -> Files relative to Output
+    #[serde(default)]
+    steps: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl Manifest {
+    fn path() -> PathBuf {
+        PathBuf::from("./.gbt/output-manifest.json")
+    }
+
+    /// Loads the manifest, or None if nothing was generated since it was introduced
+    pub fn load() -> Option<Self> {
+        fs::read_to_string(Self::path())
+            .ok()
+            .and_then(|f| serde_json::from_str(&f).ok())
+    }
+
+    fn save(&self) -> Result<()> {
+        fs::create_dir_all(Self::path().parent().unwrap())?;
+        fs::write(Self::path(), serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Every file in the manifest
+    pub fn files(&self) -> BTreeSet<&str> {
+        self.steps.values().flatten().map(|f| f.as_str()).collect()
+    }
+
+    /// Files in `output` that no step generated
+    pub fn unlisted(&self, output: &Path) -> Vec<PathBuf> {
+        let files = self.files();
+        WalkDir::new(output)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_map(|f| f.ok())
+            .filter(|f| f.file_type().is_file())
+            .map(|f| f.into_path())
+            .filter(|f| !files.contains(relative(output, f).as_str()))
+            .collect()
+    }
+}
+
+/// Path of a file in `output` as it is recorded in the manifest
+fn relative(output: &Path, path: &Path) -> String {
+    path.strip_prefix(output)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+/// Replaces the files recorded for `step`, or adds to them if the step only
+/// generated part of its files
+pub fn record(step: &str, files: impl IntoIterator<Item = String>, complete: bool) -> Result<()> {
+    let mut manifest = Manifest::load().unwrap_or_default();
+    let recorded = manifest.steps.entry(step.to_owned()).or_default();
+    if complete {
+        recorded.clear();
+    }
+    recorded.extend(files);
+    manifest.save().context("Failed to Save Output Manifest")
+}
+
+/// Drops the `removed` files of `output` from every step, so they are not
+/// reported as missing
+pub fn forget(output: &Path, removed: &[PathBuf]) -> Result<()> {
+    let Some(mut manifest) = Manifest::load() else {
+        return Ok(());
+    };
+    let removed = removed
+        .iter()
+        .map(|f| relative(output, f))
+        .collect::<BTreeSet<_>>();
+    manifest
+        .steps
+        .values_mut()
+        .for_each(|files| files.retain(|f| !removed.contains(f)));
+    manifest.save().context("Failed to Save Output Manifest")
+}
+
+/// Files in `output` that should be shipped. Warns about the rest, and ships
+/// everything if no manifest was recorded yet
+pub fn shipped(output: &Path) -> Vec<PathBuf> {
+    let Some(manifest) = Manifest::load() else {
+        warn!("No Output Manifest Found. Run build and gen-ini to Record one. Shipping all of Output");
+        return WalkDir::new(output)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_map(|f| f.ok())
+            .filter(|f| f.file_type().is_file())
+            .map(|f| f.into_path())
+            .collect();
+    };
+    for path in manifest.unlisted(output) {
+        warn!(
+            "{:} was not Generated by GBT and will not be Shipped. Remove it with `gbt clean --orphans`",
+            path.display()
+        );
+    }
+    manifest
+        .files()
+        .into_iter()
+        .map(|f| output.join(f))
+        .filter(|f| {
+            let exists = f.is_file();
+            if !exists {
+                warn!("{:} is in the Output Manifest but Missing", f.display());
+            }
+            exists
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_unlisted() {
+        let tmp = tempfile::tempdir().unwrap();
+        let output = tmp.path();
+        fs::create_dir_all(output.join("Shaders")).unwrap();
+        for file in ["Body.dds", "Renamed.dds", "Mod.ini", "Shaders/ps.txt"] {
+            fs::write(output.join(file), "").unwrap();
+        }
+        let manifest = Manifest {
+            steps: BTreeMap::from([
+                ("build".to_owned(), BTreeSet::from(["Body.dds".to_owned()])),
+                (
+                    "gen-ini".to_owned(),
+                    BTreeSet::from(["Mod.ini".to_owned(), "Shaders/ps.txt".to_owned()]),
+                ),
+            ]),
+        };
+        assert_eq!(manifest.unlisted(output), vec![output.join("Renamed.dds")]);
+    }
+}
@@ -0,0 +1,499 @@
+use anyhow::{Context, Error, Result};
+use itertools::Itertools;
+use log::{error, info, trace, warn};
+use normpath::PathExt;
+use regex::{Captures, Regex};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    process::exit,
+};
+use walkdir::WalkDir;
+
+use crate::utils::{
+    ignore::IgnoreRules,
+    ini::{Expr, Ini, IniDocument, IniSegment, SectionKind, Statement},
+};
+
+use super::{archive::run_zip, config::Config, mods::is_disabled};
+
+/// Variable cycling between the merged mods
+const SWAP_VAR: &str = "swapvar";
+
+/// Section prefixes 3DMigoto recognises. The namespace is inserted right after them
+const PREFIXES: [&str; 7] = [
+    "TextureOverride",
+    "ShaderOverride",
+    "ShaderRegex",
+    "CommandList",
+    "CustomShader",
+    "Resource",
+    "Key",
+];
+
+/// Override fields that select what is matched, rather than being run as a command list
+fn is_match_key(key: &str) -> bool {
+    let key = key.to_lowercase();
+    key == "hash"
+        || key == "filter_index"
+        || key == "allow_duplicate_hash"
+        || key.starts_with("match_")
+}
+
+struct Mod {
+    namespace: String,
+    path: PathBuf,
+    segments: Vec<IniSegment>,
+}
+
+impl Mod {
+    /// Loads a project's Output, or a mod folder
+    fn load(path: &Path) -> Result<(String, PathBuf)> {
+        let config_path = path.join("Config.yml");
+        if config_path.exists() {
+            let config = serde_yaml::from_str::<Config>(&fs::read_to_string(&config_path)?)
+                .with_context(|| format!("Failed to Parse {:}", config_path.display()))?;
+            return Ok((config.project_name, path.join("Output")));
+        }
+        let name = path
+            .normalize()?
+            .as_path()
+            .file_name()
+            .map(|f| f.to_string_lossy().to_string())
+            .unwrap_or_default();
+        Ok((name, path.to_path_buf()))
+    }
+
+    fn read(name: &str, path: &Path, namespaces: &mut HashSet<String>) -> Result<Self> {
+        let base = name
+            .chars()
+            .filter(|f| f.is_ascii_alphanumeric() || *f == '_')
+            .collect::<String>();
+        let mut namespace = base.to_owned();
+        let mut index = 1;
+        while !namespaces.insert(namespace.to_lowercase()) {
+            index += 1;
+            namespace = format!("{:}{:}", base, index);
+        }
+
+        let inis = fs::read_dir(path)
+            .with_context(|| format!("Failed to Read {:}", path.display()))?
+            .filter_map(|f| f.ok())
+            .map(|f| f.path())
+            .filter(|f| {
+                f.extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("ini"))
+            })
+            .filter(|f| !is_disabled(f))
+            .sorted()
+            .collect::<Vec<_>>();
+        if inis.is_empty() {
+            return Err(Error::msg(format!("No ini found in {:}", path.display())));
+        }
+        let mut segments: Vec<IniSegment> = vec![];
+        for ini in inis {
+            trace!("Reading {:}", ini.display());
+            let document = IniDocument::read(&ini)
+                .with_context(|| format!("Failed to Parse {:}", ini.display()))?;
+            for seg in document.segments() {
+                let is_shared = matches!(
+                    seg.header().to_lowercase().as_str(),
+                    "constants" | "present"
+                );
+                match segments
+                    .iter()
+                    .find(|f| f.header().eq_ignore_ascii_case(seg.header()))
+                {
+                    Some(_) if is_shared => segments.push(seg),
+                    Some(existing) if existing.statements() == seg.statements() => (),
+                    Some(_) => warn!(
+                        "{:} defines [{:}] more than once. Keeping the first",
+                        name,
+                        seg.header()
+                    ),
+                    None => segments.push(seg),
+                }
+            }
+        }
+        Ok(Self {
+            namespace,
+            path: path.to_path_buf(),
+            segments,
+        })
+    }
+
+    fn namespaced(&self, header: &str) -> String {
+        PREFIXES
+            .iter()
+            .find(|prefix| {
+                header.len() >= prefix.len() && header[..prefix.len()].eq_ignore_ascii_case(prefix)
+            })
+            .map(|prefix| {
+                format!(
+                    "{:}{:}{:}",
+                    &header[..prefix.len()],
+                    self.namespace,
+                    &header[prefix.len()..]
+                )
+            })
+            .unwrap_or_else(|| format!("{:}{:}", self.namespace, header))
+    }
+}
+
+/// Renames the sections, variables and files a mod refers to into its namespace
+struct Renamer<'a> {
+    namespace: &'a str,
+    sections: HashMap<String, String>,
+    token: Regex,
+    variable: Regex,
+}
+
+impl Renamer<'_> {
+    fn text(&self, text: &str) -> String {
+        let text = self.token.replace_all(text, |c: &Captures| {
+            self.sections
+                .get(&c[0].to_lowercase())
+                .cloned()
+                .unwrap_or_else(|| c[0].to_owned())
+        });
+        self.variable
+            .replace_all(&text, |c: &Captures| {
+                format!("${:}_{:}", self.namespace.to_lowercase(), &c[1])
+            })
+            .to_string()
+    }
+
+    fn expr(&self, expr: &Expr) -> Expr {
+        Expr::Value(self.text(&expr.to_string()).into())
+    }
+
+    fn statements(&self, statements: &[Statement]) -> Vec<Statement> {
+        statements.iter().map(|f| self.statement(f)).collect()
+    }
+
+    fn statement(&self, statement: &Statement) -> Statement {
+        match statement {
+            Statement::Field(key, value) if key.eq_ignore_ascii_case("filename") => {
+                Statement::Field(
+                    key.clone(),
+                    format!("{:}/{:}", self.namespace, value).into(),
+                )
+            }
+            Statement::Field(key, value) => {
+                Statement::Field(self.text(key).into(), self.text(value).into())
+            }
+            Statement::Global {
+                persist,
+                name,
+                value,
+            } => Statement::Global {
+                persist: *persist,
+                name: format!("{:}_{:}", self.namespace.to_lowercase(), name).into(),
+                value: self.expr(value),
+            },
+            Statement::Set(name, value) => Statement::Set(
+                format!(
+                    "{:}_{:}",
+                    self.namespace.to_lowercase(),
+                    name.trim_start_matches('$')
+                )
+                .into(),
+                self.expr(value),
+            ),
+            Statement::Run(command_list) => Statement::Run(self.text(command_list).into()),
+            Statement::Command(command) => Statement::Command(self.text(command).into()),
+            Statement::Pre(statement) => Statement::Pre(Box::new(self.statement(statement))),
+            Statement::Post(statement) => Statement::Post(Box::new(self.statement(statement))),
+            Statement::If {
+                condition,
+                then,
+                otherwise,
+            } => Statement::If {
+                condition: self.expr(condition),
+                then: self.statements(then),
+                otherwise: self.statements(otherwise),
+            },
+        }
+    }
+}
+
+fn is_override(header: &str) -> bool {
+    let header = header.to_lowercase();
+    header.starts_with("textureoverride") || header.starts_with("shaderoverride")
+}
+
+fn swap_condition(index: usize) -> Expr {
+    Expr::var(SWAP_VAR).equals(Expr::value(index))
+}
+
+/// Chains the command lists of each mod into `if $swapvar == n` branches
+fn swap_chain(branches: Vec<(usize, Vec<Statement>)>) -> Vec<Statement> {
+    branches
+        .into_iter()
+        .filter(|(_, then)| !then.is_empty())
+        .rev()
+        .fold(vec![], |otherwise, (index, then)| {
+            vec![Statement::If {
+                condition: swap_condition(index),
+                then,
+                otherwise,
+            }]
+        })
+}
+
+/// `(header, matching fields, command list of each mod)`
+type MergedOverride = (String, Vec<Statement>, Vec<(usize, Vec<Statement>)>);
+
+/// Merges the mods into one ini, swapping between them with `key`
+fn merge_mods(mods: &[Mod], name: &str, key: &str) -> Ini {
+    let token = Regex::new(r"[A-Za-z_][A-Za-z0-9_.]*").unwrap();
+    let variable = Regex::new(r"\$([A-Za-z_][A-Za-z0-9_]*)").unwrap();
+
+    // Overrides matching the same draw are merged into one section
+    let mut overrides: Vec<MergedOverride> = vec![];
+    let mut ini = Ini::new(name, mods.iter().map(|f| f.namespace.to_owned()).join(", "))
+        .add_constant(Statement::Global {
+            persist: true,
+            name: SWAP_VAR.into(),
+            value: Expr::value(0),
+        })
+        .add_section(
+            SectionKind::Key,
+            IniSegment::new("KeySwap")
+                .add_field("key", key)
+                .add_field("type", "cycle")
+                .add_field(&format!("${:}", SWAP_VAR), &(0..mods.len()).join(",")),
+        );
+
+    for (index, merged_mod) in mods.iter().enumerate() {
+        let renamer = Renamer {
+            namespace: &merged_mod.namespace,
+            sections: merged_mod
+                .segments
+                .iter()
+                .filter(|f| !is_override(f.header()))
+                .map(|f| (f.header().to_lowercase(), merged_mod.namespaced(f.header())))
+                .collect(),
+            token: token.clone(),
+            variable: variable.clone(),
+        };
+        for seg in &merged_mod.segments {
+            let header = seg.header();
+            let statements = renamer.statements(seg.statements());
+            match header.to_lowercase().as_str() {
+                "constants" => {
+                    for statement in statements {
+                        ini = ini.add_constant(statement);
+                    }
+                }
+                "present" => {
+                    ini = ini.add_present(Statement::If {
+                        condition: swap_condition(index),
+                        then: statements,
+                        otherwise: vec![],
+                    })
+                }
+                lower if is_override(lower) => {
+                    let (matching, command_list): (Vec<_>, Vec<_>) = statements
+                        .into_iter()
+                        .partition(|f| matches!(f, Statement::Field(key, _) if is_match_key(key)));
+                    match overrides
+                        .iter_mut()
+                        .find(|f| f.1 == matching && f.2.iter().all(|(i, _)| *i != index))
+                    {
+                        Some((_, _, branches)) => branches.push((index, command_list)),
+                        None => overrides.push((
+                            header.to_owned(),
+                            matching,
+                            vec![(index, command_list)],
+                        )),
+                    }
+                }
+                lower => {
+                    let kind = match lower {
+                        f if f.starts_with("resource") => SectionKind::Resource,
+                        f if f.starts_with("commandlist") || f.starts_with("customshader") => {
+                            SectionKind::CommandList
+                        }
+                        f if f.starts_with("key") => SectionKind::Key,
+                        _ => {
+                            warn!("Merging [{:}] of {:} as is", header, merged_mod.namespace);
+                            SectionKind::Override
+                        }
+                    };
+                    let mut merged = IniSegment::new(&merged_mod.namespaced(header));
+                    // Keys only fire while their mod is selected
+                    if kind == SectionKind::Key {
+                        let condition = statements.iter().find_map(|f| match f {
+                            Statement::Field(key, value)
+                                if key.eq_ignore_ascii_case("condition") =>
+                            {
+                                Some(value.to_string())
+                            }
+                            _ => None,
+                        });
+                        let condition = match condition {
+                            Some(condition) => {
+                                format!("({:}) && ({:})", swap_condition(index), condition)
+                            }
+                            None => swap_condition(index).to_string(),
+                        };
+                        merged = merged.add_field("condition", &condition);
+                    }
+                    for statement in statements {
+                        if !matches!(&statement, Statement::Field(key, _) if kind == SectionKind::Key && key.eq_ignore_ascii_case("condition"))
+                        {
+                            merged = merged.add_statement(statement);
+                        }
+                    }
+                    ini = ini.add_section(kind, merged);
+                }
+            }
+        }
+    }
+
+    let mut headers = HashSet::new();
+    for (header, matching, branches) in overrides {
+        let header = match headers.insert(header.to_lowercase()) {
+            true => header,
+            false => {
+                let mut index = 2;
+                while !headers.insert(format!("{:}{:}", header, index).to_lowercase()) {
+                    index += 1;
+                }
+                format!("{:}{:}", header, index)
+            }
+        };
+        let mut seg = IniSegment::new(&header);
+        for statement in matching.into_iter().chain(swap_chain(branches)) {
+            seg = seg.add_statement(statement);
+        }
+        ini = ini.add_override(seg);
+    }
+    ini
+}
+
+/// Copies everything but the inis of a mod into its namespace folder
+fn copy_files(merged_mod: &Mod, output: &Path) -> Result<()> {
+    let target = output.join(&merged_mod.namespace);
+    for entry in WalkDir::new(&merged_mod.path)
+        .into_iter()
+        .filter_map(|f| f.ok())
+    {
+        let path = entry.path();
+        if !path.is_file()
+            || path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("ini"))
+        {
+            continue;
+        }
+        let target_path = target.join(path.strip_prefix(&merged_mod.path)?);
+        fs::create_dir_all(target_path.parent().unwrap())?;
+        fs::copy(path, &target_path)
+            .with_context(|| format!("Failed to Copy {:}", path.display()))?;
+    }
+    Ok(())
+}
+
+pub fn merge(paths: &[PathBuf], name: &str, key: &str, output: &Path, export: Option<&PathBuf>) {
+    let mut namespaces = HashSet::new();
+    let mods = paths
+        .iter()
+        .map(|path| {
+            let (mod_name, mod_path) = Mod::load(path)?;
+            info!("Reading {:} from {:}", mod_name, mod_path.display());
+            Mod::read(&mod_name, &mod_path, &mut namespaces)
+        })
+        .collect::<Result<Vec<_>>>()
+        .unwrap_or_else(|err| {
+            error!("{:#}", err);
+            exit(1);
+        });
+    if mods.len() < 2 {
+        warn!("Merging a single Mod. It will only be namespaced");
+    }
+
+    fs::create_dir_all(output).expect("Failed to Create Output");
+    for merged_mod in &mods {
+        copy_files(merged_mod, output).expect("Failed to Copy Mod Files");
+    }
+    let ini_path = output.join(format!("{:}.ini", name));
+    merge_mods(&mods, name, key)
+        .export(&ini_path)
+        .expect("Failed to Write Merged Ini");
+    info!("Merged {:} Mods into {:}", mods.len(), ini_path.display());
+
+    if let Some(export) = export {
+        let zip_path = export
+            .normalize()
+            .unwrap()
+            .into_path_buf()
+            .join(format!("{}.zip", name));
+        info!("Exporting Merged Mod to {:}", zip_path.display());
+        run_zip(output, &zip_path, None, &IgnoreRules::default());
+        info!("Mod Exported");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn test_mod(namespace: &str, ini: &str) -> Mod {
+        Mod {
+            namespace: namespace.to_owned(),
+            path: PathBuf::new(),
+            segments: IniDocument::from_str(ini).unwrap().segments(),
+        }
+    }
+
+    #[test]
+    pub fn test_merge_mods() {
+        let ini = "[Constants]
+global $active = 0
+[KeyGlow]
+key = VK_F1
+$active = 0,1
+[TextureOverrideBody]
+hash = 1a2b3c4d
+match_first_index = 0
+if $active == 1
+    ps-t0 = ResourceBody
+endif
+[ResourceBody]
+filename = Body.dds
+";
+        let merged = merge_mods(
+            &[test_mod("Red", ini), test_mod("Blue", ini)],
+            "Skins",
+            "VK_F5",
+        )
+        .to_string();
+        assert!(merged.contains(
+            "global persist $swapvar = 0\nglobal $red_active=0\nglobal $blue_active=0\n"
+        ));
+        assert!(merged.contains("[KeySwap]\nkey=VK_F5\ntype=cycle\n$swapvar=0,1\n"));
+        assert!(
+            merged.contains("[KeyRedGlow]\ncondition=$swapvar == 0\nkey=VK_F1\n$red_active=0,1\n")
+        );
+        assert!(merged.contains(
+            "[TextureOverrideBody]
+hash=1a2b3c4d
+match_first_index=0
+if $swapvar == 0
+    if $red_active == 1
+        ps-t0=ResourceRedBody
+    endif
+else if $swapvar == 1
+    if $blue_active == 1
+        ps-t0=ResourceBlueBody
+    endif
+endif
+"
+        ));
+        assert!(merged.contains("[ResourceBlueBody]\nfilename=Blue/Body.dds\n"));
+    }
+}
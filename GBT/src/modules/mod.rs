@@ -2,10 +2,25 @@ pub mod scaffold;
 pub mod config;
 pub mod git;
 mod dds;
+mod layers;
 pub mod watcher;
 pub mod build;
+pub mod cache;
+pub mod clean;
 pub mod script;
 pub mod gen_ini;
+pub mod variants;
+pub mod model;
+pub mod hash;
+pub mod rehash;
+pub mod buffer;
+pub mod verify;
+pub mod lint;
+pub mod check;
+pub mod dump;
 pub mod linker;
+pub mod manifest;
+pub mod mods;
 pub mod archive;
+pub mod merge;
 pub mod updater;
\ No newline at end of file
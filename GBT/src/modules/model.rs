@@ -0,0 +1,379 @@
+use anyhow::{Context, Error, Result};
+use log::{error, info, trace};
+use std::{fs, path::Path, str::FromStr};
+
+use crate::utils::ini::{Ini, IniSegment};
+
+use super::{
+    buffer::{DxgiFormat, Fmt, IndexBuffer, VertexBuffer},
+    hash::{character_name, load_components, validate_components, Component},
+};
+
+/// Format GBT writes every merged IB in
+const IB_FORMAT: &str = "DXGI_FORMAT_R32_UINT";
+
+#[derive(Default)]
+struct ModelIni {
+    vb_overrides: Vec<IniSegment>,
+    ib_overrides: Vec<IniSegment>,
+    vb_resources: Vec<IniSegment>,
+    ib_resources: Vec<IniSegment>,
+    tex_resources: Vec<IniSegment>,
+    // Buffers written to the output
+    files: Vec<String>,
+}
+
+/// Binds the part's textures onto its override, or onto overrides matched by
+/// the texture hashes themselves for texture only parts and Faces
+fn add_textures(
+    ini: &mut ModelIni,
+    component: &Component,
+    current_name: &str,
+    index: usize,
+    object_override: Option<IniSegment>,
+) {
+    let current_object = component.object_name(index);
+    let textures = component.texture_hashes(index);
+    let mut object_override = match (object_override, component.is_face()) {
+        (Some(seg), true) => {
+            ini.ib_overrides.push(seg);
+            None
+        }
+        (seg, _) => seg,
+    };
+    for (slot, texture) in textures.iter().enumerate() {
+        let tex_type = &texture.name;
+        let resource = format!("Resource{:}{:}{:}", current_name, current_object, tex_type);
+        let slot_key = format!("ps-t{:}", slot);
+        object_override = match object_override {
+            Some(seg) => Some(seg.add_field(&slot_key, &resource)),
+            None => {
+                ini.ib_overrides.push(
+                    IniSegment::new(&format!(
+                        "TextureOverride{:}{:}{:}",
+                        current_name, current_object, tex_type
+                    ))
+                    .add_field("hash", &texture.hash)
+                    .add_field(&slot_key, &resource),
+                );
+                None
+            }
+        };
+        ini.tex_resources.push(IniSegment::new(&resource).add_field(
+            "filename",
+            &format!(
+                "{:}{:}{:}{:}",
+                current_name, current_object, tex_type, texture.extension
+            ),
+        ));
+    }
+    ini.ib_overrides.extend(object_override);
+}
+
+fn assemble_component(
+    source: &Path,
+    output: &Path,
+    character: &str,
+    component: &Component,
+    ini: &mut ModelIni,
+) -> Result<()> {
+    let current_name = format!("{:}{:}", character, component.component_name);
+    info!("Assembling {:}", current_name);
+
+    // Components without draw vbs are texture overrides only
+    if !component.has_draw() {
+        for index in 0..component.object_indexes.len() {
+            add_textures(ini, component, &current_name, index, None);
+        }
+        return Ok(());
+    }
+
+    let fmt_path = source.join(format!(
+        "{:}{:}.fmt",
+        current_name, component.object_classifications[0]
+    ));
+    let fmt = Fmt::read(&fmt_path)
+        .with_context(|| format!("Failed to Read {:}", fmt_path.display()))?;
+    let ib_format = fmt.format.clone().unwrap_or(DxgiFormat::from_str(IB_FORMAT)?);
+
+    let mut merged = VertexBuffer::new(fmt.stride, vec![])?;
+    ini.ib_overrides.push(
+        IniSegment::new(&format!("TextureOverride{:}IB", current_name))
+            .add_field("hash", &component.ib)
+            .add_field("handling", "skip")
+            .add_field("drawindexed", "auto"),
+    );
+
+    for (index, first_index) in component.object_indexes.iter().enumerate() {
+        let current_object = component.object_name(index);
+        trace!("Collecting {:}{:}", current_name, current_object);
+
+        let vb_path = source.join(format!("{:}{:}.vb", current_name, current_object));
+        let vb = VertexBuffer::read(&vb_path, fmt.stride)
+            .with_context(|| format!("Failed to Read {:}", vb_path.display()))?;
+        // The IB is offset by the vertices of every part merged before it
+        let offset = u32::try_from(merged.vertex_count())
+            .with_context(|| format!("Too many Vertices to Merge {:}", vb_path.display()))?;
+        merged.append(&vb)?;
+
+        let ib_path = source.join(format!("{:}{:}.ib", current_name, current_object));
+        let mut ib = IndexBuffer::read(&ib_path, ib_format.clone())
+            .with_context(|| format!("Failed to Read {:}", ib_path.display()))?
+            .with_format(DxgiFormat::from_str(IB_FORMAT)?)?;
+        ib.offset(offset)
+            .with_context(|| format!("Failed to Merge {:}", ib_path.display()))?;
+        let ib_file = format!("{:}{:}.ib", current_name, current_object);
+        ib.write(&output.join(&ib_file))?;
+        ini.files.push(ib_file);
+
+        let ib_resource = format!("Resource{:}{:}IB", current_name, current_object);
+        let object_override = Some(
+            IniSegment::new(&format!("TextureOverride{:}{:}", current_name, current_object))
+                .add_field("hash", &component.ib)
+                .add_field("match_first_index", &first_index.to_string())
+                .add_field(
+                    "ib",
+                    match ib.indices.is_empty() {
+                        true => "null",
+                        false => ib_resource.as_str(),
+                    },
+                ),
+        );
+        ini.ib_resources.push(
+            IniSegment::new(&ib_resource)
+                .add_field("type", "Buffer")
+                .add_field("format", IB_FORMAT)
+                .add_field("filename", &format!("{:}{:}.ib", current_name, current_object)),
+        );
+        add_textures(ini, component, &current_name, index, object_override);
+    }
+
+    if component.has_blend() {
+        let streams = merged.split().with_context(|| {
+            format!(
+                "Stride {:} of {:} is too small to split into Position, Blend and Texcoord",
+                fmt.stride, current_name
+            )
+        })?;
+        let kinds = ["Position", "Blend", "Texcoord"];
+        for (kind, stream) in kinds.iter().zip(&streams) {
+            let buf_file = format!("{:}{:}.buf", current_name, kind);
+            stream.write(&output.join(&buf_file))?;
+            ini.files.push(buf_file);
+        }
+        ini.vb_overrides.push(
+            IniSegment::new(&format!("TextureOverride{:}Position", current_name))
+                .add_field("hash", &component.position_vb)
+                .add_field("vb0", &format!("Resource{:}Position", current_name)),
+        );
+        ini.vb_overrides.push(
+            IniSegment::new(&format!("TextureOverride{:}Blend", current_name))
+                .add_field("hash", &component.blend_vb)
+                .add_field("vb1", &format!("Resource{:}Blend", current_name))
+                .add_field("handling", "skip")
+                .add_field("draw", &format!("{:},0", streams[0].vertex_count())),
+        );
+        ini.vb_overrides.push(
+            IniSegment::new(&format!("TextureOverride{:}Texcoord", current_name))
+                .add_field("hash", &component.texcoord_vb)
+                .add_field("vb1", &format!("Resource{:}Texcoord", current_name)),
+        );
+        ini.vb_overrides.push(
+            IniSegment::new(&format!("TextureOverride{:}VertexLimitRaise", current_name))
+                .add_field("hash", &component.draw_vb),
+        );
+        for (kind, stream) in kinds.iter().zip(&streams) {
+            ini.vb_resources.push(
+                IniSegment::new(&format!("Resource{:}{:}", current_name, kind))
+                    .add_field("type", "Buffer")
+                    .add_field("stride", &stream.stride.to_string())
+                    .add_field("filename", &format!("{:}{:}.buf", current_name, kind)),
+            );
+        }
+    } else {
+        let buf_file = format!("{:}.buf", current_name);
+        merged.write(&output.join(&buf_file))?;
+        ini.files.push(buf_file);
+        ini.vb_overrides.push(
+            IniSegment::new(&format!("TextureOverride{:}", current_name))
+                .add_field("hash", &component.draw_vb)
+                .add_field("vb0", &format!("Resource{:}", current_name)),
+        );
+        ini.vb_resources.push(
+            IniSegment::new(&format!("Resource{:}", current_name))
+                .add_field("type", "Buffer")
+                .add_field("stride", &fmt.stride.to_string())
+                .add_field("filename", &format!("{:}.buf", current_name)),
+        );
+    }
+    Ok(())
+}
+
+/// Merges the exported parts in `source` into the buffers 3DMigoto loads,
+/// writing them to `output` and adding their overrides and resources to `ini`.
+/// The buffers written are added to `products`
+pub fn assemble_model(
+    source: &Path,
+    output: &Path,
+    mut ini: Ini,
+    products: &mut Vec<String>,
+) -> Result<Ini> {
+    let components = load_components(source)?;
+    let errors = validate_components(source, &components);
+    if !errors.is_empty() {
+        errors.iter().for_each(|err| error!("{err}"));
+        return Err(Error::msg("Model Dump is Malformed"));
+    }
+    let character = character_name(source, &components)
+        .ok_or_else(|| Error::msg("Failed to find a .fmt for any Component in hash.json"))?;
+    fs::create_dir_all(output)?;
+
+    let mut model_ini = ModelIni::default();
+    for component in &components {
+        assemble_component(source, output, &character, component, &mut model_ini)?;
+    }
+    products.append(&mut model_ini.files);
+
+    for seg in model_ini.vb_overrides.into_iter().chain(model_ini.ib_overrides) {
+        ini = ini.add_override(seg);
+    }
+    for seg in model_ini
+        .vb_resources
+        .into_iter()
+        .chain(model_ini.ib_resources)
+        .chain(model_ini.tex_resources)
+    {
+        ini = ini.add_resource(seg);
+    }
+    Ok(ini)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::buffer::{BLEND_STRIDE, POSITION_STRIDE};
+
+    /// Writes a part as exported from Blender, with vertices filled with `first..`
+    fn write_part(source: &Path, name: &str, stride: usize, first: u8, vertices: u8, indices: Vec<u32>) {
+        let data = (first..first + vertices)
+            .flat_map(|f| vec![f; stride])
+            .collect();
+        VertexBuffer::new(stride, data)
+            .unwrap()
+            .write(&source.join(format!("{:}.vb", name)))
+            .unwrap();
+        IndexBuffer::new(DxgiFormat::from_str("DXGI_FORMAT_R16_UINT").unwrap(), indices)
+            .unwrap()
+            .write(&source.join(format!("{:}.ib", name)))
+            .unwrap();
+    }
+
+    #[test]
+    pub fn test_assemble_model() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (source, output) = (tmp.path().join("Model"), tmp.path().join("Output"));
+        fs::create_dir_all(&source).unwrap();
+        // A skinned Body of three parts, the last of them empty, a Face and a
+        // texture only Dress
+        fs::write(
+            source.join("hash.json"),
+            r#"[
+    {"component_name": "Body", "draw_vb": "0a000001", "position_vb": "0b000001", "blend_vb": "0c000001",
+     "texcoord_vb": "0d000001", "ib": "0e000001", "object_indexes": [0, 30, 60],
+     "texture_hashes": [[["Diffuse", ".dds", "0f000001"]], [["Diffuse", ".dds", "0f000002"]], []]},
+    {"component_name": "Face", "draw_vb": "1a000001", "position_vb": "1b000001", "ib": "1e000001",
+     "object_indexes": [0],
+     "texture_hashes": [[["Diffuse", ".dds", "1f000001"], ["LightMap", ".dds", "1f000002"]]]},
+    {"component_name": "Dress", "object_indexes": [0], "texture_hashes": [[["Diffuse", ".dds", "2f000001"]]]}
+]"#,
+        )
+        .unwrap();
+        let stride = POSITION_STRIDE + BLEND_STRIDE + 8;
+        fs::write(
+            source.join("ImpBodyHead.fmt"),
+            format!("stride: {:}\ntopology: trianglelist\nformat: DXGI_FORMAT_R16_UINT\n", stride),
+        )
+        .unwrap();
+        write_part(&source, "ImpBodyHead", stride, 0, 3, vec![0, 1, 2]);
+        write_part(&source, "ImpBodyBody", stride, 3, 3, vec![0, 2, 1]);
+        write_part(&source, "ImpBodyExtra", stride, 6, 0, vec![]);
+        fs::write(
+            source.join("ImpFaceHead.fmt"),
+            "stride: 12\ntopology: trianglelist\nformat: DXGI_FORMAT_R16_UINT\n",
+        )
+        .unwrap();
+        write_part(&source, "ImpFaceHead", 12, 0, 3, vec![2, 1, 0]);
+        for texture in ["ImpBodyHeadDiffuse", "ImpBodyBodyDiffuse", "ImpFaceHeadDiffuse", "ImpDressHeadDiffuse"] {
+            fs::write(source.join(format!("{:}.dds", texture)), "").unwrap();
+        }
+
+        let mut products = vec![];
+        let ini = assemble_model(&source, &output, Ini::new("Imp", "A".to_owned()), &mut products)
+            .unwrap()
+            .to_string();
+
+        // Every part's IB is offset by the vertices merged before it
+        let ib = |name: &str| {
+            IndexBuffer::read(&output.join(name), DxgiFormat::from_str(IB_FORMAT).unwrap())
+                .unwrap()
+                .indices
+        };
+        assert_eq!(ib("ImpBodyHead.ib"), vec![0, 1, 2]);
+        assert_eq!(ib("ImpBodyBody.ib"), vec![3, 5, 4]);
+        assert!(ib("ImpBodyExtra.ib").is_empty());
+        // The merged vertices are split into Position, Blend and Texcoord
+        for (kind, size) in [("Position", POSITION_STRIDE), ("Blend", BLEND_STRIDE), ("Texcoord", 8)] {
+            assert_eq!(
+                fs::read(output.join(format!("ImpBody{:}.buf", kind))).unwrap(),
+                (0..6).flat_map(|f| vec![f; size]).collect::<Vec<u8>>()
+            );
+        }
+        assert_eq!(
+            fs::read(output.join("ImpFace.buf")).unwrap(),
+            (0..3).flat_map(|f| vec![f; 12]).collect::<Vec<u8>>()
+        );
+        assert_eq!(
+            products,
+            vec![
+                "ImpBodyHead.ib",
+                "ImpBodyBody.ib",
+                "ImpBodyExtra.ib",
+                "ImpBodyPosition.buf",
+                "ImpBodyBlend.buf",
+                "ImpBodyTexcoord.buf",
+                "ImpFaceHead.ib",
+                "ImpFace.buf",
+            ]
+        );
+
+        for section in [
+            "[TextureOverrideImpBodyPosition]\nhash=0b000001\nvb0=ResourceImpBodyPosition\n",
+            "[TextureOverrideImpBodyBlend]\nhash=0c000001\nvb1=ResourceImpBodyBlend\nhandling=skip\ndraw=6,0\n",
+            "[TextureOverrideImpBodyTexcoord]\nhash=0d000001\nvb1=ResourceImpBodyTexcoord\n",
+            "[TextureOverrideImpBodyVertexLimitRaise]\nhash=0a000001\n",
+            "[TextureOverrideImpBodyIB]\nhash=0e000001\nhandling=skip\ndrawindexed=auto\n",
+            "[TextureOverrideImpBodyHead]\nhash=0e000001\nmatch_first_index=0\nib=ResourceImpBodyHeadIB\n\
+             ps-t0=ResourceImpBodyHeadDiffuse\n",
+            "[TextureOverrideImpBodyBody]\nhash=0e000001\nmatch_first_index=30\nib=ResourceImpBodyBodyIB\n\
+             ps-t0=ResourceImpBodyBodyDiffuse\n",
+            // Empty parts are hidden
+            "[TextureOverrideImpBodyExtra]\nhash=0e000001\nmatch_first_index=60\nib=null\n\n",
+            "[ResourceImpBodyPosition]\ntype=Buffer\nstride=40\nfilename=ImpBodyPosition.buf\n",
+            "[ResourceImpBodyTexcoord]\ntype=Buffer\nstride=8\nfilename=ImpBodyTexcoord.buf\n",
+            "[ResourceImpBodyBodyIB]\ntype=Buffer\nformat=DXGI_FORMAT_R32_UINT\nfilename=ImpBodyBody.ib\n",
+            "[ResourceImpBodyHeadDiffuse]\nfilename=ImpBodyHeadDiffuse.dds\n",
+            // Components without Blend keep a single VB
+            "[TextureOverrideImpFace]\nhash=1a000001\nvb0=ResourceImpFace\n",
+            "[ResourceImpFace]\ntype=Buffer\nstride=12\nfilename=ImpFace.buf\n",
+            // Faces only replace their Diffuse, matched by its hash
+            "[TextureOverrideImpFaceHead]\nhash=1e000001\nmatch_first_index=0\nib=ResourceImpFaceHeadIB\n\n",
+            "[TextureOverrideImpFaceHeadDiffuse]\nhash=1f000001\nps-t0=ResourceImpFaceHeadDiffuse\n",
+            // Texture only components are matched by their texture hashes
+            "[TextureOverrideImpDressHeadDiffuse]\nhash=2f000001\nps-t0=ResourceImpDressHeadDiffuse\n",
+            "[ResourceImpDressHeadDiffuse]\nfilename=ImpDressHeadDiffuse.dds\n",
+        ] {
+            assert!(ini.contains(section), "{:} is missing from\n{:}", section, ini);
+        }
+        assert!(!ini.contains("LightMap"));
+    }
+}
@@ -0,0 +1,203 @@
+use itertools::Itertools;
+use log::{error, info, trace, warn};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    process::exit,
+};
+use walkdir::WalkDir;
+
+use crate::{utils::ini::IniDocument, CONFIG};
+
+use super::config::does_config_exist;
+
+/// Whether 3DMigoto skips a mod folder or ini because of its `DISABLED` prefix
+pub fn is_disabled(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|f| f.to_string_lossy().to_uppercase().starts_with("DISABLED"))
+}
+
+/// Override section matching a hash
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashOverride {
+    pub hash: String,
+    /// Folder under Mods the ini belongs to
+    pub mod_name: String,
+    pub ini: PathBuf,
+    pub section: String,
+    pub line: usize,
+}
+
+/// Reads the hash of every override in `ini`
+fn read_overrides(mod_name: &str, ini: &Path) -> Vec<HashOverride> {
+    let document = match IniDocument::read(ini) {
+        Ok(document) => document,
+        Err(err) => {
+            warn!("Skipping {:}: {:}", ini.display(), err);
+            return vec![];
+        }
+    };
+    document
+        .sections()
+        .into_iter()
+        .filter(|f| {
+            let name = f.name.to_lowercase();
+            name.starts_with("textureoverride") || name.starts_with("shaderoverride")
+        })
+        .filter_map(|section| {
+            Some(HashOverride {
+                hash: section.get("hash")?.trim().to_lowercase(),
+                mod_name: mod_name.to_owned(),
+                ini: ini.to_path_buf(),
+                section: section.name.to_owned(),
+                line: section.line,
+            })
+        })
+        .collect()
+}
+
+/// Reads the overrides of every active ini under `mods`, skipping the mod named `skip`
+pub fn scan_mods(mods: &Path, skip: Option<&str>) -> Vec<HashOverride> {
+    WalkDir::new(mods)
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|f| !is_disabled(f.path()))
+        .filter_map(|f| f.ok())
+        .map(|f| f.into_path())
+        .filter(|f| {
+            f.is_file()
+                && f.extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case("ini"))
+        })
+        .flat_map(|ini| {
+            let relative = ini.strip_prefix(mods).unwrap();
+            let mod_name = relative
+                .components()
+                .next()
+                .unwrap()
+                .as_os_str()
+                .to_string_lossy()
+                .to_string();
+            if skip.is_some_and(|f| f.eq_ignore_ascii_case(&mod_name)) {
+                return vec![];
+            }
+            trace!("Scanning {:}", ini.display());
+            read_overrides(&mod_name, &ini)
+        })
+        .collect()
+}
+
+/// Groups the overrides by hash, keeping the hashes matched by more than one mod
+pub fn find_conflicts(overrides: &[HashOverride]) -> BTreeMap<&str, Vec<&HashOverride>> {
+    overrides
+        .iter()
+        .into_group_map_by(|f| f.hash.as_str())
+        .into_iter()
+        .filter(|(_, group)| group.iter().map(|f| &f.mod_name).unique().count() > 1)
+        .collect()
+}
+
+pub fn conflicts() {
+    let (project_name, migoto_path) = {
+        let config = CONFIG.lock().unwrap();
+        (
+            config.project_name.to_owned(),
+            config.migoto_path.to_owned(),
+        )
+    };
+    let Some(migoto_path) = migoto_path else {
+        error!("Migoto Path Not Set. Exiting..");
+        exit(1);
+    };
+    let mods_path = migoto_path.join("Mods");
+    if !mods_path.is_dir() {
+        error!("{:} does not exist. Exiting..", mods_path.display());
+        exit(1);
+    }
+
+    // The linked copy of the project is replaced by its Output
+    let in_project = does_config_exist();
+    let mut overrides = scan_mods(&mods_path, in_project.then_some(project_name.as_str()));
+    if in_project {
+        for ini in WalkDir::new("./Output")
+            .max_depth(1)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_map(|f| f.ok())
+            .map(|f| f.into_path())
+            .filter(|f| {
+                !is_disabled(f)
+                    && f.extension()
+                        .is_some_and(|ext| ext.eq_ignore_ascii_case("ini"))
+            })
+        {
+            overrides.extend(read_overrides(&project_name, &ini));
+        }
+    }
+
+    let conflicts = find_conflicts(&overrides);
+    for (hash, group) in &conflicts {
+        let mods = group
+            .iter()
+            .map(|f| f.mod_name.as_str())
+            .unique()
+            .join(", ");
+        match in_project && group.iter().any(|f| f.mod_name == project_name) {
+            true => error!(
+                "Hash {:} of this Project is also overridden by {:}",
+                hash, mods
+            ),
+            false => warn!("Hash {:} is overridden by {:}", hash, mods),
+        }
+        for hash_override in group {
+            info!(
+                "    {:}: [{:}] ({:}:{:})",
+                hash_override.mod_name,
+                hash_override.section,
+                hash_override.ini.display(),
+                hash_override.line
+            );
+        }
+    }
+    info!(
+        "Found {:} Conflicting Hashes across {:} Overrides",
+        conflicts.len(),
+        overrides.len()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    pub fn test_find_conflicts() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mods = tmp.path();
+        let ini =
+            "[TextureOverrideBody]\nhash = 1A2B3C4D\n[TextureOverrideHead]\nhash = 5e6f7a8b\n";
+        for (folder, content) in [
+            ("Furina", ini),
+            ("Furina/Extra", "[TextureOverrideHair]\nhash = 5e6f7a8b\n"),
+            ("Nahida", "[TextureOverrideDress]\nhash = 1a2b3c4d\n"),
+            ("DISABLED Nahida", ini),
+        ] {
+            fs::create_dir_all(mods.join(folder)).unwrap();
+            fs::write(mods.join(folder).join("mod.ini"), content).unwrap();
+        }
+        let overrides = scan_mods(mods, None);
+        assert_eq!(overrides.len(), 4);
+        let conflicts = find_conflicts(&overrides);
+        assert_eq!(conflicts.keys().collect::<Vec<_>>(), vec![&"1a2b3c4d"]);
+        assert_eq!(
+            conflicts["1a2b3c4d"]
+                .iter()
+                .map(|f| f.section.as_str())
+                .collect::<Vec<_>>(),
+            vec!["TextureOverrideBody", "TextureOverrideDress"]
+        );
+        assert!(find_conflicts(&scan_mods(mods, Some("nahida"))).is_empty());
+    }
+}
@@ -0,0 +1,343 @@
+use anyhow::{Context, Error, Result};
+use itertools::Itertools;
+use log::{error, info, trace, warn};
+use serde_json::Value;
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    process::exit,
+};
+
+use crate::{utils::ini::IniDocument, CONFIG};
+
+use super::{
+    config::{does_config_exist, Config},
+    hash::is_hash,
+};
+
+/// Old to new hash pairs, and which of them were applied
+struct Mapping {
+    hashes: BTreeMap<String, String>,
+    used: RefCell<HashSet<String>>,
+    changes: RefCell<Vec<String>>,
+}
+
+impl Mapping {
+    /// Reads a mapping from a local file or a checked-in table at an http(s) url
+    fn load(map: &str) -> Result<Self> {
+        let content = match map.starts_with("http://") || map.starts_with("https://") {
+            true => minreq::get(map)
+                .send()
+                .with_context(|| format!("Failed to Fetch {:}", map))?
+                .as_str()?
+                .to_owned(),
+            false => fs::read_to_string(map).with_context(|| format!("Failed to Read {:}", map))?,
+        };
+        Self::parse(&content).with_context(|| format!("Failed to Parse {:}", map))
+    }
+
+    fn parse(content: &str) -> Result<Self> {
+        let raw = serde_yaml::from_str::<BTreeMap<String, String>>(content)?;
+        let mut hashes = BTreeMap::new();
+        for (old, new) in raw {
+            if !is_hash(&old) || !is_hash(&new) {
+                return Err(Error::msg(format!(
+                    "{:}: {:} is not a pair of hashes",
+                    old, new
+                )));
+            }
+            hashes.insert(old.to_lowercase(), new.to_lowercase());
+        }
+        Ok(Self {
+            hashes,
+            used: RefCell::new(HashSet::new()),
+            changes: RefCell::new(vec![]),
+        })
+    }
+
+    /// New hash of `old`, recording the change at `location`
+    fn get(&self, old: &str, location: impl FnOnce() -> String) -> Option<String> {
+        let new = self.hashes.get(&old.trim().to_lowercase())?;
+        self.used.borrow_mut().insert(old.trim().to_lowercase());
+        self.changes
+            .borrow_mut()
+            .push(format!("{:}: {:} -> {:}", location(), old.trim(), new));
+        Some(new.to_owned())
+    }
+
+    fn remap(&self, hash: &mut String, location: impl FnOnce() -> String) {
+        if let Some(new) = self.get(hash, location) {
+            *hash = new;
+        }
+    }
+
+    /// Renames the keys that are remapped hashes
+    fn remap_keys<V>(&self, map: HashMap<String, V>, label: &str) -> HashMap<String, V> {
+        map.into_iter()
+            .map(|(key, value)| match self.get(&key, || label.to_owned()) {
+                Some(new) => (new, value),
+                None => (key, value),
+            })
+            .collect()
+    }
+
+    fn remap_config(&self, mut config: Config) -> Config {
+        config.texture_textures = self.remap_keys(config.texture_textures, "Config.yml Textures");
+        config.model_textures = self.remap_keys(config.model_textures, "Config.yml Model Textures");
+        for (variant_name, variant) in config.variants.iter_mut() {
+            for option in variant.options.iter_mut() {
+                let label = format!("Config.yml Variants.{:}.{:}", variant_name, option.name);
+                option.textures = std::mem::take(&mut option.textures)
+                    .into_iter()
+                    .map(|(key, value)| match self.get(&key, || label.to_owned()) {
+                        Some(new) => (new, value),
+                        None => (key, value),
+                    })
+                    .collect();
+            }
+        }
+        config
+    }
+
+    /// Remaps the hashes of a `hash.json`. It is edited as json rather than as
+    /// Components, so fields GBT does not know about are kept
+    fn remap_components(&self, components: &mut Value) {
+        for (index, component) in components.as_array_mut().into_iter().flatten().enumerate() {
+            for field in ["draw_vb", "position_vb", "blend_vb", "texcoord_vb", "ib"] {
+                if let Some(Value::String(hash)) = component.get_mut(field) {
+                    self.remap(hash, || format!("hash.json [{:}].{:}", index, field));
+                }
+            }
+            let objects = component
+                .get_mut("texture_hashes")
+                .and_then(|f| f.as_array_mut())
+                .into_iter()
+                .flatten();
+            for (object, textures) in objects.enumerate() {
+                let textures = textures.as_array_mut().into_iter().flatten();
+                for (slot, texture) in textures.enumerate() {
+                    // [name, extension, hash]
+                    if let Some(Value::String(hash)) = texture.get_mut(2) {
+                        self.remap(hash, || {
+                            format!(
+                                "hash.json [{:}].texture_hashes[{:}][{:}]",
+                                index, object, slot
+                            )
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    /// Remaps the `hash` of every section. Returns whether anything changed
+    fn remap_ini(&self, ini_path: &Path, document: &mut IniDocument) -> bool {
+        let remapped = document
+            .sections()
+            .iter()
+            .filter_map(|section| {
+                let field = section
+                    .fields
+                    .iter()
+                    .find(|f| f.key.eq_ignore_ascii_case("hash"))?;
+                let new = self.get(field.value, || {
+                    format!(
+                        "{:}:{:} [{:}]",
+                        ini_path.display(),
+                        field.line,
+                        section.name
+                    )
+                })?;
+                Some((section.name.to_owned(), new))
+            })
+            .collect::<Vec<_>>();
+        for (section, new) in &remapped {
+            document.set(section, "hash", new);
+        }
+        !remapped.is_empty()
+    }
+
+    /// Mapping that reverts this one
+    fn undo(&self) -> BTreeMap<&str, &str> {
+        self.hashes
+            .iter()
+            .filter(|(old, _)| self.used.borrow().contains(*old))
+            .map(|(old, new)| (new.as_str(), old.as_str()))
+            .collect()
+    }
+}
+
+/// Writes every file, or none of them. Files are staged next to their target
+/// and only swapped in once all of them were written
+fn write_all(files: &[(PathBuf, String)]) -> Result<()> {
+    let staged = |path: &Path| {
+        path.with_file_name(format!(
+            "{:}.gbt-tmp",
+            path.file_name().unwrap().to_string_lossy()
+        ))
+    };
+    for (path, content) in files {
+        if let Err(err) = fs::write(staged(path), content) {
+            files
+                .iter()
+                .for_each(|(path, _)| drop(fs::remove_file(staged(path))));
+            return Err(err).with_context(|| format!("Failed to Write {:}", path.display()));
+        }
+    }
+    let originals = files
+        .iter()
+        .map(|(path, _)| fs::read(path).ok())
+        .collect::<Vec<_>>();
+    for (index, (path, _)) in files.iter().enumerate() {
+        if let Err(err) = fs::rename(staged(path), path) {
+            // Restore what was already swapped in
+            for ((path, _), original) in files.iter().zip(&originals).take(index) {
+                let _ = match original {
+                    Some(original) => fs::write(path, original),
+                    None => fs::remove_file(path),
+                };
+            }
+            files
+                .iter()
+                .for_each(|(path, _)| drop(fs::remove_file(staged(path))));
+            return Err(err).with_context(|| format!("Failed to Replace {:}", path.display()));
+        }
+    }
+    Ok(())
+}
+
+pub fn rehash(map: &str) {
+    if !does_config_exist() {
+        error!("Config does not exist. Exiting....");
+        exit(1);
+    }
+    let mapping = Mapping::load(map).unwrap_or_else(|err| {
+        error!("{:#}", err);
+        exit(1);
+    });
+    trace!("Mapping: {:#?}", mapping.hashes);
+
+    let mut files = vec![];
+    let original = CONFIG.lock().unwrap().to_owned();
+    let config = mapping.remap_config(original.to_owned());
+    if config != original {
+        files.push((
+            PathBuf::from("./Config.yml"),
+            serde_yaml::to_string(&config).expect("Failed to Convert Config to String"),
+        ));
+    }
+
+    let hash_path = PathBuf::from("./Source/Model/hash.json");
+    if hash_path.exists() {
+        let mut components = fs::read_to_string(&hash_path)
+            .map_err(Error::from)
+            .and_then(|f| Ok(serde_json::from_str::<Value>(&f)?))
+            .unwrap_or_else(|err| {
+                error!("{:}: {:}", hash_path.display(), err);
+                exit(1);
+            });
+        let changes = mapping.changes.borrow().len();
+        mapping.remap_components(&mut components);
+        if mapping.changes.borrow().len() > changes {
+            files.push((
+                hash_path,
+                serde_json::to_string_pretty(&components).expect("Failed to Serialize hash.json"),
+            ));
+        }
+    }
+
+    let inis = fs::read_dir("./Output")
+        .map(|dir| {
+            dir.filter_map(|f| f.ok())
+                .map(|f| f.path())
+                .filter(|f| {
+                    f.extension()
+                        .is_some_and(|ext| ext.eq_ignore_ascii_case("ini"))
+                })
+                .sorted()
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    for ini in inis {
+        let mut document = IniDocument::read(&ini).unwrap_or_else(|err| {
+            error!("{:}: {:}", ini.display(), err);
+            exit(1);
+        });
+        if mapping.remap_ini(&ini, &mut document) {
+            files.push((ini, document.to_string()));
+        }
+    }
+
+    for old in mapping
+        .hashes
+        .keys()
+        .filter(|f| !mapping.used.borrow().contains(*f))
+    {
+        warn!("{:} is not used by this Project", old);
+    }
+    let changes = mapping.changes.take();
+    if changes.is_empty() {
+        info!("Nothing to Rehash");
+        return;
+    }
+
+    let undo_path = PathBuf::from("./.gbt/rehash-undo.yml");
+    fs::create_dir_all(undo_path.parent().unwrap()).expect("Failed to Create .gbt");
+    files.push((
+        undo_path.to_owned(),
+        serde_yaml::to_string(&mapping.undo()).expect("Failed to Serialize Undo Mapping"),
+    ));
+    if let Err(err) = write_all(&files) {
+        error!("{:#}. Nothing was Changed", err);
+        exit(1);
+    }
+    *CONFIG.lock().unwrap() = config;
+
+    changes.iter().for_each(|f| info!("{:}", f));
+    info!(
+        "Rehashed {:} Hashes in {:} Places",
+        mapping.used.borrow().len(),
+        changes.len()
+    );
+    info!("Undo with `gbt rehash --map {:}`", undo_path.display());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    pub fn test_remap() {
+        let mapping = Mapping::parse("1A2B3C4D: 5e6f7a8b\n0a1b2c3d: 9a9b9c9d\n").unwrap();
+        assert!(Mapping::parse("1a2b3c4d: Body\n").is_err());
+
+        let mut document = IniDocument::from_str(
+            "[TextureOverrideBody]\nhash = 1a2b3c4d\nthis = ResourceBody\n\n[TextureOverrideHead]\nhash=ffffffff\n",
+        )
+        .unwrap();
+        assert!(mapping.remap_ini(Path::new("Mod.ini"), &mut document));
+        assert_eq!(
+            document.to_string(),
+            "[TextureOverrideBody]\nhash = 5e6f7a8b\nthis = ResourceBody\n\n[TextureOverrideHead]\nhash=ffffffff\n"
+        );
+        assert_eq!(
+            mapping.changes.borrow().as_slice(),
+            ["Mod.ini:2 [TextureOverrideBody]: 1a2b3c4d -> 5e6f7a8b"]
+        );
+        assert_eq!(mapping.undo(), BTreeMap::from([("5e6f7a8b", "1a2b3c4d")]));
+
+        let mut components = serde_json::from_str::<Value>(
+            r#"[{"draw_vb": "0a1b2c3d", "ib": "ffffffff", "notes": "Kept",
+                "texture_hashes": [[["Diffuse", ".dds", "1a2b3c4d"]]]}]"#,
+        )
+        .unwrap();
+        mapping.remap_components(&mut components);
+        assert_eq!(
+            components.to_string(),
+            r#"[{"draw_vb":"9a9b9c9d","ib":"ffffffff","notes":"Kept","texture_hashes":[[["Diffuse",".dds","5e6f7a8b"]]]}]"#
+        );
+    }
+}
@@ -3,18 +3,19 @@ use log::{info, trace};
 use rayon::prelude::*;
 use regex::Regex;
 use std::{
-    collections::{HashMap, HashSet},
-    fs::create_dir_all,
-    path::PathBuf,
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{self, create_dir_all},
+    path::{Path, PathBuf},
     str::FromStr,
     sync::Arc,
 };
 
 use crate::{
     get_confirm,
-    modules::{config::ProjectType, dds::{generate_tex_split, gen_hash_tex_unit}},
+    modules::{config::{ProjectType, ShaderStage, ShaderUnit}, dds::{generate_tex_split, gen_hash_tex_unit}},
     utils::{
         copy::copy,
+        ignore::DEFAULT_GBTIGNORE,
         interactions::{get_input_string, get_input_string_with_validator, get_multi_input},
     },
     CONFIG,
@@ -64,13 +65,14 @@ fn scaffold_model(project_path: PathBuf) -> Result<()> {
                 .collect();
         }
     }
+    let split_rules = CONFIG.lock().unwrap().split_rules.clone();
     let tex_units = files
         .par_iter()
         .filter(|f| f.extension().unwrap_or_default().to_str().unwrap_or("") == "dds")
         .map(|f| {
             (
                 f.file_stem().unwrap().to_str().unwrap().to_string(),
-                generate_tex_split(f.to_path_buf(), &project_path, &PathBuf::from("./Textures/Model"))
+                generate_tex_split(f.to_path_buf(), &project_path, &PathBuf::from("./Textures/Model"), &split_rules)
                     .expect("Failed to Generate Texture Unit"),
             )
         })
@@ -121,6 +123,59 @@ fn scaffold_textures(project_path: PathBuf) -> Result<()> {
     Ok(())
 }
 
+/// Shader units of the shaders replaced in `source_path`, as they are found once
+/// it is copied into Source/Shader
+fn find_replaced_shaders(source_path: &Path) -> Result<HashMap<String, ShaderUnit>> {
+    // 3DMigoto names replaced shaders as {hash}-{vs|ps}_replace.txt
+    let re = Regex::new(r"^([0-9a-fA-F]{16})-(vs|ps)_replace\.txt$").unwrap();
+    let mut shader_units = HashMap::new();
+    for entry in source_path.read_dir()? {
+        let file_name = entry?.file_name().to_string_lossy().to_string();
+        let Some(captures) = re.captures(&file_name) else {
+            continue;
+        };
+        shader_units.insert(
+            captures[1].to_lowercase(),
+            ShaderUnit {
+                stage: ShaderStage::from_str(&captures[2]).unwrap(),
+                path: PathBuf::from("./Source/Shader").join(&file_name),
+                resources: BTreeMap::new(),
+            },
+        );
+    }
+    Ok(shader_units)
+}
+
+fn scaffold_shaders(project_path: PathBuf) -> Result<()> {
+    let source_path = get_input_string_with_validator(
+        "Path to your ShaderFixes (Leave Empty to Start Fresh)",
+        None,
+        |input: &String| -> Result<(), &str> {
+            if input.is_empty() || PathBuf::from(input).exists() {
+                Ok(())
+            } else {
+                Err("Path does not exist. Enter a Valid Path")
+            }
+        },
+    );
+    if source_path.is_empty() {
+        create_dir_all(project_path.join("Source/Shader"))?;
+        CONFIG.lock().unwrap().shaders = HashMap::new();
+        return Ok(());
+    }
+    let source_path = PathBuf::from(source_path);
+    let should_symlink = get_confirm!(
+        "Should Symlink Source? (Requires Admin, or SeCreateSymbolicLinkPrivilege)",
+        false
+    );
+    copy(&source_path, &project_path.join("Source/Shader"), should_symlink)?;
+
+    let shader_units = find_replaced_shaders(&source_path)?;
+    info!("Found {:} Replaced Shaders", shader_units.len());
+    CONFIG.lock().unwrap().shaders = shader_units;
+    Ok(())
+}
+
 pub fn scaffold(project_path: PathBuf) {
     trace!("In Scaffold Module");
     create_dir_all(&project_path).expect("Failed to Create Project at Specifed Path");
@@ -194,9 +249,45 @@ pub fn scaffold(project_path: PathBuf) {
             }
             ProjectType::Texture => scaffold_textures(project_path.clone())
                 .expect("Failed to Complete Texture Scaffolding"),
-            ProjectType::Shader => (),
+            ProjectType::Shader => scaffold_shaders(project_path.clone())
+                .expect("Failed to Complete Shader Scaffolding"),
         }
     }
     info!("Project Successfully Scaffolded");
+    let ignore_path = project_path.join(".gbtignore");
+    if !ignore_path.exists() {
+        fs::write(ignore_path, DEFAULT_GBTIGNORE).expect("Failed to Write .gbtignore");
+    }
     CONFIG.lock().unwrap().save_project_conf(Some(project_path));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_find_replaced_shaders() {
+        let tmp = tempfile::tempdir().unwrap();
+        for file in [
+            "0123456789ABCDEF-vs_replace.txt",
+            "fedcba9876543210-ps_replace.txt",
+            "0123456789abcdef-vs.txt",
+            "ShaderCache.bin",
+        ] {
+            fs::write(tmp.path().join(file), "").unwrap();
+        }
+        let shader_units = find_replaced_shaders(tmp.path()).unwrap();
+        let mut hashes = shader_units.keys().collect::<Vec<_>>();
+        hashes.sort();
+        assert_eq!(hashes, vec!["0123456789abcdef", "fedcba9876543210"]);
+        assert_eq!(
+            shader_units["0123456789abcdef"],
+            ShaderUnit {
+                stage: ShaderStage::Vertex,
+                path: PathBuf::from("./Source/Shader/0123456789ABCDEF-vs_replace.txt"),
+                resources: BTreeMap::new(),
+            }
+        );
+        assert_eq!(shader_units["fedcba9876543210"].stage, ShaderStage::Pixel);
+    }
+}
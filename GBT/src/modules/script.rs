@@ -5,15 +5,15 @@ use log::{error, info, trace};
 use std::io::{stderr, stdout, Write};
 use std::{
     collections::VecDeque,
+    ffi::OsString,
     io::{BufRead, BufReader},
-    path::PathBuf,
     process::exit,
     slice::Iter,
     str::FromStr,
 };
 use subprocess::{Popen, PopenConfig};
 
-use super::config::does_config_exist;
+use super::{build::PROFILE_VAR, config::does_config_exist, watcher::WatchEvent};
 
 #[derive(Debug, Clone, Default, PartialEq)]
 enum Operator {
@@ -107,15 +107,27 @@ impl FromStr for ScriptParser {
 }
 
 pub trait Run {
-    fn run(&self, debug_level: u8);
+    fn run(&self, debug_level: u8, env: &[(&str, &str)]);
 }
 
-fn run_none(command: Vec<&str>) {
+/// Environment of the commands, with `env` set over the one GBT runs in
+fn command_env(env: &[(&str, &str)]) -> Option<Vec<(OsString, OsString)>> {
+    if env.is_empty() {
+        return None;
+    }
+    let mut vars = PopenConfig::current_env();
+    vars.retain(|(key, _)| !env.iter().any(|(name, _)| key == name));
+    vars.extend(env.iter().map(|(key, value)| (key.into(), value.into())));
+    Some(vars)
+}
+
+fn run_none(command: Vec<&str>, env: &[(&str, &str)]) {
     trace!("Trying to run next command {:#?}", command);
     let mut p = Popen::create(
         &command,
         PopenConfig {
             // stdout: subprocess::Redirection::Pipe,
+            env: command_env(env),
             ..Default::default()
         },
     )
@@ -141,10 +153,11 @@ fn run_none(command: Vec<&str>) {
     }
 }
 
-fn run_and(command: Vec<&str>, segment: &ScriptParser, debug_level:u8) {
+fn run_and(command: Vec<&str>, segment: &ScriptParser, debug_level:u8, env: &[(&str, &str)]) {
     let mut p = Popen::create(
         &command,
         PopenConfig {
+            env: command_env(env),
             ..Default::default()
         },
     )
@@ -162,7 +175,7 @@ fn run_and(command: Vec<&str>, segment: &ScriptParser, debug_level:u8) {
             if status.success() {
                 match &segment.next_segment {
                     Some(next_command) => {
-                        next_command.run(debug_level);
+                        next_command.run(debug_level, env);
                     }
                     None => {
                         error!("process: {:#?}; Exited with {:#?}", command, status);
@@ -178,10 +191,11 @@ fn run_and(command: Vec<&str>, segment: &ScriptParser, debug_level:u8) {
     }
 }
 
-fn run_or(command: Vec<&str>, segment: &ScriptParser, debug_level:u8) {
+fn run_or(command: Vec<&str>, segment: &ScriptParser, debug_level:u8, env: &[(&str, &str)]) {
     let mut p = Popen::create(
         &command,
         PopenConfig {
+            env: command_env(env),
             ..Default::default()
         },
     )
@@ -203,7 +217,7 @@ fn run_or(command: Vec<&str>, segment: &ScriptParser, debug_level:u8) {
             error!("Popen Failed on: {:#?}; Errored with {:#?}", command, err);
             match &segment.next_segment {
                 Some(next_command) => {
-                    next_command.run(debug_level);
+                    next_command.run(debug_level, env);
                 }
                 None => (),
             }
@@ -212,7 +226,7 @@ fn run_or(command: Vec<&str>, segment: &ScriptParser, debug_level:u8) {
 }
 
 impl Run for ScriptParser {
-    fn run(&self, debug_level: u8) {
+    fn run(&self, debug_level: u8, env: &[(&str, &str)]) {
         trace!("Running");
         let mut d = "-".to_owned();
         let exectuable = Exectuable::new();
@@ -232,14 +246,15 @@ impl Run for ScriptParser {
         let mut args: Vec<_> = self.args.iter().map(|f| f.as_str()).collect();
         command.append(&mut args);
         match self.operator {
-            Operator::And => run_and(command, self, debug_level),
-            Operator::None => run_none(command),
-            Operator::Or => run_or(command, self, debug_level),
+            Operator::And => run_and(command, self, debug_level, env),
+            Operator::None => run_none(command, env),
+            Operator::Or => run_or(command, self, debug_level, env),
             Operator::Pipe => {
                 let mut p = Popen::create(
                     &command,
                     PopenConfig {
                         stdout: subprocess::Redirection::Pipe,
+                        env: command_env(env),
                         ..Default::default()
                     },
                 )
@@ -253,14 +268,15 @@ impl Run for ScriptParser {
                     let std_out = BufReader::new(output);
 
                     for line in std_out.lines() {
-                        let val = serde_json::from_str::<PathBuf>(line.unwrap().as_str()).unwrap();
-                        info!("New Line: {:#?}", val);
-                        if diff(val.to_str().unwrap(), "./Config.yml") {
+                        let event = serde_json::from_str::<WatchEvent>(line.unwrap().as_str()).unwrap();
+                        info!("New Line: {:#?}", event.path);
+                        if diff(event.path.to_str().unwrap(), "./Config.yml") {
                             CONFIG.lock().unwrap().load_project_conf();
                         } else {
                             match &self.next_segment {
                                 Some(next_command) => {
-                                    next_command.run(debug_level);
+                                    // Builds triggered by the watcher use its profile
+                                    next_command.run(debug_level, &[(PROFILE_VAR, &event.profile)]);
                                 }
                                 None => (),
                             }
@@ -278,5 +294,5 @@ pub fn run_script(script: String, debug_level: u8) {
         exit(1);
     }
     let parser = CONFIG.lock().unwrap().scripts_parsed.get(&script).unwrap().to_owned();
-    parser.run(debug_level);
+    parser.run(debug_level, &[]);
 }
@@ -0,0 +1,233 @@
+use anyhow::{Error, Result};
+use itertools::Itertools;
+use std::collections::{HashMap, HashSet};
+
+use crate::utils::ini::{Expr, Ini, IniSegment, SectionKind, Statement};
+
+use super::config::{KeyType, TexUnit, Variant};
+
+/// Name of the `$var` holding the active option of a variant
+fn variable(variant_name: &str) -> String {
+    let name = variant_name
+        .chars()
+        .filter(|f| f.is_ascii_alphanumeric() || *f == '_')
+        .collect::<String>();
+    format!("swap_{:}", name.to_lowercase())
+}
+
+/// Name the TexUnit of an option is built and bound as
+fn texture_name(texture: &str, variant_name: &str, option_name: &str) -> String {
+    format!("{:}{:}{:}", texture, variant_name, option_name).replace(' ', "")
+}
+
+/// TexUnits of every variant option, keyed by the name they are built as
+pub fn variant_textures(variants: &HashMap<String, Variant>) -> HashMap<String, TexUnit> {
+    variants
+        .iter()
+        .flat_map(|(variant_name, variant)| {
+            variant.options.iter().flat_map(move |option| {
+                option.textures.iter().map(move |(texture, tex_unit)| {
+                    (
+                        texture_name(texture, variant_name, &option.name),
+                        tex_unit.to_owned(),
+                    )
+                })
+            })
+        })
+        .collect()
+}
+
+/// Rewrites every statement `replace` matches, recursing into conditionals
+fn rewrite(statements: &mut [Statement], replace: &mut dyn FnMut(&Statement) -> Option<Statement>) {
+    for statement in statements.iter_mut() {
+        if let Statement::If { then, otherwise, .. } = statement {
+            rewrite(then, replace);
+            rewrite(otherwise, replace);
+        } else if let Some(replaced) = replace(statement) {
+            *statement = replaced;
+        }
+    }
+}
+
+/// Chains `if $var == n` over the options, falling back to `default`
+fn option_chain(var: &str, options: Vec<Option<Statement>>, default: Statement) -> Statement {
+    options
+        .into_iter()
+        .enumerate()
+        .rev()
+        .fold(default, |otherwise, (index, statement)| match statement {
+            Some(then) => Statement::If {
+                condition: Expr::var(var).equals(Expr::value(index)),
+                then: vec![then],
+                otherwise: vec![otherwise],
+            },
+            None => otherwise,
+        })
+}
+
+/// Swaps the variant's textures wherever their Resource is bound, and hides its
+/// parts by nulling their IB. Returns the textures and parts it found
+fn apply_variant(ini: &mut Ini, variant_name: &str, variant: &Variant) -> HashSet<String> {
+    let var = variable(variant_name);
+    let textures = variant
+        .options
+        .iter()
+        .flat_map(|f| f.textures.keys())
+        .unique()
+        .collect::<Vec<_>>();
+    let parts = variant
+        .options
+        .iter()
+        .flat_map(|f| &f.parts)
+        .unique()
+        .collect::<Vec<_>>();
+
+    let mut matched = HashSet::new();
+    for seg in ini.segments_mut() {
+        let part = seg
+            .header()
+            .strip_prefix("TextureOverride")
+            .and_then(|f| parts.iter().find(|part| part.as_str() == f))
+            .map(|f| f.to_string());
+        rewrite(seg.statements_mut(), &mut |statement| {
+            let Statement::Field(key, value) = statement else {
+                return None;
+            };
+            if let Some(texture) = textures.iter().find(|f| value.as_ref() == format!("Resource{:}", f)) {
+                matched.insert(texture.to_string());
+                let options = variant
+                    .options
+                    .iter()
+                    .map(|option| {
+                        option.textures.contains_key(*texture).then(|| {
+                            Statement::Field(
+                                key.clone(),
+                                format!("Resource{:}", texture_name(texture, variant_name, &option.name)).into(),
+                            )
+                        })
+                    })
+                    .collect();
+                return Some(option_chain(&var, options, statement.clone()));
+            }
+            if let (Some(part), "ib") = (&part, key.as_ref()) {
+                matched.insert(part.to_owned());
+                let options = variant
+                    .options
+                    .iter()
+                    .map(|option| (!option.parts.contains(part)).then(|| Statement::Field(key.clone(), "null".into())))
+                    .collect();
+                return Some(option_chain(&var, options, statement.clone()));
+            }
+            None
+        });
+    }
+    matched
+}
+
+/// Wraps the textures and parts each variant swaps in `$var` conditionals, and
+/// adds the keys cycling them. Returns every texture and part that was found
+///
+/// `owners` holds the ini each variant was applied to so far. A variant swapping
+/// things in two inis would get a key and persisted `$var` in each, which drift
+/// apart, so it is rejected instead
+pub fn apply_variants(
+    mut ini: Ini,
+    variants: &HashMap<String, Variant>,
+    ini_file: &str,
+    owners: &mut HashMap<String, String>,
+) -> Result<(Ini, HashSet<String>)> {
+    let mut matched = HashSet::new();
+    for (variant_name, variant) in variants.iter().sorted_by_key(|f| f.0) {
+        if variant.options.is_empty() {
+            return Err(Error::msg(format!("Variant {:} has no Options", variant_name)));
+        }
+        if variant.key_type != KeyType::Cycle && variant.options.len() != 2 {
+            return Err(Error::msg(format!(
+                "Variant {:} uses a {:} key, which needs exactly 2 Options",
+                variant_name, variant.key_type
+            )));
+        }
+        let found = apply_variant(&mut ini, variant_name, variant);
+        if found.is_empty() {
+            continue;
+        }
+        if let Some(owner) = owners.insert(variant_name.to_owned(), ini_file.to_owned()) {
+            return Err(Error::msg(format!(
+                "Variant {:} swaps things in both {:} and {:}, which would give it a key in each. Split it into a Variant per ini",
+                variant_name, owner, ini_file
+            )));
+        }
+        for option in &variant.options {
+            for texture in option.textures.keys().filter(|f| found.contains(*f)) {
+                let name = texture_name(texture, variant_name, &option.name);
+                ini = ini.add_resource(
+                    IniSegment::new(&format!("Resource{:}", name)).add_field("filename", &format!("{:}.dds", name)),
+                );
+            }
+        }
+        matched.extend(found);
+        let var = variable(variant_name);
+        ini = ini
+            .add_constant(Statement::Global {
+                persist: true,
+                name: var.as_str().into(),
+                value: Expr::value(0),
+            })
+            .add_section(
+                SectionKind::Key,
+                IniSegment::new(&format!("KeySwap{:}", variant_name.replace(' ', "")))
+                    .add_field("key", &variant.key)
+                    .add_field("type", &variant.key_type.to_string())
+                    .add_field(&format!("${:}", var), &(0..variant.options.len()).join(",")),
+            );
+    }
+    Ok((ini, matched))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_apply_variants() {
+        let variants: HashMap<String, Variant> = serde_yaml::from_str(
+            "Hair:
+  key: VK_F7
+  type: toggle
+  options:
+  - name: Default
+  - name: Blonde
+    textures:
+      1a2b3c4d: { paths: [./Blonde.png], encoding: BC7Unorm }
+",
+        )
+        .unwrap();
+        let ini = Ini::new("Mod", "A".to_owned())
+            .add_override(
+                IniSegment::new("TextureOverride1a2b3c4d")
+                    .add_field("hash", "1a2b3c4d")
+                    .add_field("this", "Resource1a2b3c4d"),
+            )
+            .add_resource(IniSegment::new("Resource1a2b3c4d").add_field("filename", "1a2b3c4d.dds"));
+        let mut owners = HashMap::new();
+        let (ini, matched) = apply_variants(ini, &variants, "Mod_Texture.ini", &mut owners).unwrap();
+        assert_eq!(matched, HashSet::from(["1a2b3c4d".to_owned()]));
+        assert_eq!(variant_textures(&variants).keys().collect::<Vec<_>>(), vec!["1a2b3c4dHairBlonde"]);
+        let content = ini.to_string();
+        assert!(content.contains("[KeySwapHair]\nkey=VK_F7\ntype=toggle\n$swap_hair=0,1\n"));
+        assert!(content.contains(
+            "if $swap_hair == 1\n    this=Resource1a2b3c4dHairBlonde\nelse\n    this=Resource1a2b3c4d\nendif\n"
+        ));
+        assert!(content.contains("[Resource1a2b3c4dHairBlonde]\nfilename=1a2b3c4dHairBlonde.dds\n"));
+
+        // Another ini binding the same texture cannot get a second key for the variant
+        let model = Ini::new("Mod", "A".to_owned()).add_override(
+            IniSegment::new("TextureOverrideBody")
+                .add_field("hash", "5e6f7a8b")
+                .add_field("ps-t0", "Resource1a2b3c4d"),
+        );
+        assert!(apply_variants(model, &variants, "Mod_Model.ini", &mut owners).is_err());
+        let shader = Ini::new("Mod", "A".to_owned());
+        assert!(apply_variants(shader, &variants, "Mod_Shader.ini", &mut owners).is_ok());
+    }
+}
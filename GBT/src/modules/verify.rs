@@ -0,0 +1,294 @@
+use log::{error, info, trace};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    process::exit,
+    str::FromStr,
+};
+use thiserror::Error;
+
+use crate::utils::ini::IniDocument;
+
+use super::{
+    buffer::{DxgiFormat, IndexBuffer},
+    config::does_config_exist,
+    manifest::shipped,
+};
+
+#[derive(Error, Debug)]
+pub enum VerifyError {
+    /// Failed to read an ini or buffer
+    ///
+    /// `(path, error message)`
+    #[error("{0:?}: {1}")]
+    Io(PathBuf, String),
+
+    /// Resource pointing to a file that does not exist
+    ///
+    /// `(ini path, section, missing file)`
+    #[error("{0:?}: [{1}]: Missing file {2:?}")]
+    MissingFile(PathBuf, String, PathBuf),
+
+    /// Buffer whose length does not fit its declared stride or format
+    ///
+    /// `(ini path, section, error message)`
+    #[error("{0:?}: [{1}]: {2}")]
+    Layout(PathBuf, String, String),
+
+    /// IB indexing past the end of the VB it is drawn with
+    ///
+    /// `(ini path, section, largest index, vb section, vertex count)`
+    #[error("{0:?}: [{1}]: Index {2} is out of range of [{3}] with {4} vertices")]
+    IndexOutOfRange(PathBuf, String, u32, String, usize),
+
+    /// `draw = N,0` not matching the vertex count of the bound buffers
+    ///
+    /// `(ini path, section, draw count, vb section, vertex count)`
+    #[error("{0:?}: [{1}]: draw = {2} does not match [{3}] with {4} vertices")]
+    DrawMismatch(PathBuf, String, usize, String, usize),
+}
+
+type Section = (String, Vec<(String, String)>);
+
+/// Reads the fields of every section, with their keys lowercased
+fn read_sections(path: &Path) -> Result<Vec<Section>, VerifyError> {
+    let ini = IniDocument::read(path).map_err(|e| VerifyError::Io(path.to_path_buf(), e.to_string()))?;
+    Ok(ini
+        .sections()
+        .into_iter()
+        .map(|section| {
+            (
+                section.name.to_owned(),
+                section
+                    .fields
+                    .iter()
+                    .map(|f| (f.key.to_lowercase(), f.value.to_owned()))
+                    .collect(),
+            )
+        })
+        .collect())
+}
+
+struct BufferInfo {
+    section: String,
+    /// Number of vertices, or indices for IBs
+    count: usize,
+    max_index: Option<u32>,
+}
+
+fn field<'a>(fields: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    fields.iter().find(|f| f.0 == key).map(|f| f.1.as_str())
+}
+
+/// Checks a resource against its stride, or against its format if it is bound as
+/// an `ib`. Other resources (textures, typed buffers, etc.) are not checked
+fn check_resource(
+    ini_path: &Path,
+    section: &str,
+    fields: &[(String, String)],
+    is_ib: bool,
+) -> Result<Option<BufferInfo>, VerifyError> {
+    let (Some(filename), true) = (
+        field(fields, "filename"),
+        field(fields, "stride").is_some() || is_ib,
+    ) else {
+        return Ok(None);
+    };
+    let path = ini_path.parent().unwrap_or(Path::new(".")).join(filename);
+    if !path.exists() {
+        return Err(VerifyError::MissingFile(ini_path.to_path_buf(), section.to_owned(), path));
+    }
+    let data = fs::read(&path).map_err(|e| VerifyError::Io(path.clone(), e.to_string()))?;
+    let layout_error = |message: String| VerifyError::Layout(ini_path.to_path_buf(), section.to_owned(), message);
+
+    if let Some(stride) = field(fields, "stride") {
+        let stride = stride
+            .parse::<usize>()
+            .ok()
+            .filter(|f| *f > 0)
+            .ok_or_else(|| layout_error(format!("Invalid stride {:?}", stride)))?;
+        if !data.len().is_multiple_of(stride) {
+            return Err(layout_error(format!(
+                "{:?} has length {} which does not match stride {}",
+                filename,
+                data.len(),
+                stride
+            )));
+        }
+        return Ok(Some(BufferInfo {
+            section: section.to_owned(),
+            count: data.len() / stride,
+            max_index: None,
+        }));
+    }
+
+    let format = field(fields, "format").unwrap_or_default();
+    let format = DxgiFormat::from_str(format).map_err(|e| layout_error(e.to_string()))?;
+    let ib = IndexBuffer::from_bytes(format, &data)
+        .map_err(|e| layout_error(format!("{:?}: {}", filename, e)))?;
+    Ok(Some(BufferInfo {
+        section: section.to_owned(),
+        count: ib.indices.len(),
+        max_index: ib.indices.iter().max().copied(),
+    }))
+}
+
+/// Finds the VBs the IB bound in `fields` can be drawn with. 3DMigoto does not
+/// record this, so an override binding its own `vb0` is drawn with that one, and
+/// any other may be drawn with every VB the ini binds as `vb0`
+fn position_buffers<'a>(
+    fields: &[(String, String)],
+    buffers: &'a HashMap<String, BufferInfo>,
+    vb0: &[&'a BufferInfo],
+) -> Vec<&'a BufferInfo> {
+    match field(fields, "vb0").and_then(|f| buffers.get(&f.to_lowercase())) {
+        Some(vb) => vec![vb],
+        None => vb0.to_vec(),
+    }
+}
+
+fn verify_ini(ini_path: &Path) -> Vec<VerifyError> {
+    trace!("Verifying {:?}", ini_path);
+    let sections = match read_sections(ini_path) {
+        Ok(sections) => sections,
+        Err(err) => return vec![err],
+    };
+    let bound = |slot: &str| {
+        sections
+            .iter()
+            .filter_map(|(_, fields)| field(fields, slot))
+            .map(|resource| resource.to_lowercase())
+            .collect::<HashSet<_>>()
+    };
+    let ibs = bound("ib");
+    let mut errors = vec![];
+    let mut buffers = HashMap::new();
+    for (section, fields) in &sections {
+        let is_ib = ibs.contains(&section.to_lowercase());
+        match check_resource(ini_path, section, fields, is_ib) {
+            Ok(Some(info)) => {
+                buffers.insert(section.to_lowercase(), info);
+            }
+            Ok(None) => (),
+            Err(err) => errors.push(err),
+        }
+    }
+
+    let vb0 = bound("vb0")
+        .iter()
+        .filter_map(|resource| buffers.get(resource))
+        .collect::<Vec<_>>();
+    for (_, fields) in &sections {
+        let Some(ib) = field(fields, "ib").and_then(|f| buffers.get(&f.to_lowercase())) else {
+            continue;
+        };
+        let Some(max_index) = ib.max_index else {
+            continue;
+        };
+        // Only out of range of every VB it may be drawn with is an error
+        let vbs = position_buffers(fields, &buffers, &vb0);
+        if let Some(vb) = vbs
+            .iter()
+            .max_by_key(|vb| vb.count)
+            .filter(|vb| max_index as usize >= vb.count)
+        {
+            errors.push(VerifyError::IndexOutOfRange(
+                ini_path.to_path_buf(),
+                ib.section.to_owned(),
+                max_index,
+                vb.section.to_owned(),
+                vb.count,
+            ));
+        }
+    }
+
+    for (section, fields) in &sections {
+        let Some(draw) = field(fields, "draw")
+            .and_then(|f| f.split(',').next())
+            .and_then(|f| f.trim().parse::<usize>().ok())
+        else {
+            continue;
+        };
+        let vbs = fields
+            .iter()
+            .filter(|(key, _)| key.starts_with("vb"))
+            .filter_map(|(_, resource)| buffers.get(&resource.to_lowercase()));
+        for vb in vbs {
+            if vb.count != draw {
+                errors.push(VerifyError::DrawMismatch(
+                    ini_path.to_path_buf(),
+                    section.to_owned(),
+                    draw,
+                    vb.section.to_owned(),
+                    vb.count,
+                ));
+            }
+        }
+    }
+    errors
+}
+
+/// Checks every buffer the shipped inis among `files` declare against its stride and format
+pub fn verify_output(files: &[PathBuf]) -> Vec<VerifyError> {
+    files
+        .iter()
+        .filter(|f| f.extension().is_some_and(|ext| ext == "ini"))
+        .flat_map(|ini| verify_ini(ini))
+        .collect()
+}
+
+/// Logs every error found in the shipped `files`, returning whether they passed
+pub fn check_output(files: &[PathBuf]) -> bool {
+    let errors = verify_output(files);
+    errors.iter().for_each(|err| error!("{err}"));
+    errors.is_empty()
+}
+
+pub fn verify() {
+    if !does_config_exist() {
+        error!("Config does not exist. Exiting....");
+        exit(1);
+    }
+    if !check_output(&shipped(Path::new("./Output"))) {
+        error!("Verification Failed");
+        exit(1);
+    }
+    info!("Verification Passed");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_verify_ini() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        // 3 vertices, an IB indexing the 4th and a typed buffer of 3 bytes
+        fs::write(dir.join("Body.buf"), [0; 12]).unwrap();
+        fs::write(dir.join("Body.ib"), [0, 0, 0, 0, 3, 0, 0, 0]).unwrap();
+        fs::write(dir.join("Data.buf"), [0; 3]).unwrap();
+        fs::write(
+            dir.join("Mod.ini"),
+            "[TextureOverrideBody]\nhash = 0a1b2c3d\nvb0 = ResourceBody\nib = ResourceBodyIB\n\n\
+             [TextureOverrideShader]\nhash = 1a1b2c3d\nps-t10 = ResourceData\n\n\
+             [ResourceBody]\ntype = Buffer\nstride = 4\nfilename = Body.buf\n\n\
+             [ResourceBodyIB]\ntype = Buffer\nformat = DXGI_FORMAT_R32_UINT\nfilename = Body.ib\n\n\
+             [ResourceData]\ntype = Buffer\nformat = DXGI_FORMAT_R32_UINT\nfilename = Data.buf\n",
+        )
+        .unwrap();
+        let errors = verify_ini(&dir.join("Mod.ini"));
+
+        assert_eq!(errors.len(), 1);
+        assert!(matches!(
+            &errors[0],
+            VerifyError::IndexOutOfRange(_, section, 3, _, 3) if section == "ResourceBodyIB"
+        ));
+
+        // Inis that are not shipped are not verified
+        fs::write(dir.join("Old.ini"), "[ResourceOld]\ntype = Buffer\nstride = 5\nfilename = Body.buf\n").unwrap();
+        assert_eq!(verify_output(&[dir.join("Mod.ini"), dir.join("Body.buf")]).len(), 1);
+        assert_eq!(verify_output(&[dir.join("Old.ini")]).len(), 1);
+    }
+}
@@ -1,10 +1,11 @@
 use crate::CONFIG;
 
-use super::config::does_config_exist;
+use super::{config::does_config_exist, variants::variant_textures};
 use file_diff::diff;
 use log::{error, info, trace};
 use notify_debouncer_full::{new_debouncer, notify::*};
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use serde_json;
 use std::{
     collections::HashSet,
@@ -13,6 +14,14 @@ use std::{
     time::Duration,
 };
 
+/// Written for every changed file, and read by the script the watcher is piped into
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WatchEvent {
+    pub path: PathBuf,
+    // Build Profile of the rebuilds the change triggers
+    pub profile: String,
+}
+
 fn paths(files: &mut Vec<String>, paths: &Vec<PathBuf>) -> Option<PathBuf> {
     for path in paths {
         let is_match = files.par_iter().any(|file| {
@@ -56,8 +65,7 @@ fn build_watch_files(files: &mut Vec<String>) {
         .unwrap()
         .model_textures
         .values()
-        .map(|f| f.paths.to_vec())
-        .flatten()
+        .flat_map(|f| f.sources())
         .map(|path| path.to_str().unwrap().to_owned())
         .collect::<Vec<_>>();
     files.append(&mut tex);
@@ -67,15 +75,31 @@ fn build_watch_files(files: &mut Vec<String>) {
         .unwrap()
         .texture_textures
         .values()
-        .map(|f| f.paths.to_vec())
-        .flatten()
+        .flat_map(|f| f.sources())
+        .map(|path| path.to_str().unwrap().to_owned())
+        .collect::<Vec<_>>();
+    files.append(&mut tex);
+
+    tex = variant_textures(&CONFIG.lock().unwrap().variants)
+        .values()
+        .flat_map(|f| f.sources())
         .map(|path| path.to_str().unwrap().to_owned())
         .collect::<Vec<_>>();
     files.append(&mut tex);
+
+    let mut shaders = CONFIG
+        .lock()
+        .unwrap()
+        .shaders
+        .values()
+        .flat_map(|f| std::iter::once(f.path.to_owned()).chain(f.resources.values().cloned()))
+        .map(|path| path.to_str().unwrap().to_owned())
+        .collect::<Vec<_>>();
+    files.append(&mut shaders);
     info!("Watch Files Built");
 }
 
-pub fn watch() {
+pub fn watch(profile: &str) {
     if !does_config_exist() {
         error!("Config does not exist in current directory. Exiting");
         exit(1);
@@ -109,7 +133,13 @@ pub fn watch() {
                 .map(|f| f.unwrap())
                 .collect::<HashSet<_>>()
                 .par_iter()
-                .for_each(|path| println!("{:}", serde_json::to_string(path).unwrap())),
+                .for_each(|path| {
+                    let event = WatchEvent {
+                        path: path.to_owned(),
+                        profile: profile.to_owned(),
+                    };
+                    println!("{:}", serde_json::to_string(&event).unwrap())
+                }),
             Err(errors) => errors.iter().for_each(|error| println!("{error:?}")),
         }
     }
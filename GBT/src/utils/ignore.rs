@@ -0,0 +1,110 @@
+use glob::{MatchOptions, Pattern};
+use log::warn;
+use std::{fs, path::Path};
+
+/// Written to `.gbtignore` by `gbt init`, and used for projects without one
+pub const DEFAULT_GBTIGNORE: &str = "# Paths left out of `gbt archive`, in .gitignore syntax
+.git/
+.gbt/
+Textures/Cache/
+Output/
+";
+
+#[derive(Debug, Clone)]
+struct Rule {
+    pattern: Pattern,
+    negated: bool,
+    dir_only: bool,
+    // Patterns with a slash match from the root, the others match names at any depth
+    anchored: bool,
+}
+
+/// Gitignore style rules. The last rule matching a path decides whether it is ignored
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules {
+    rules: Vec<Rule>,
+}
+
+impl IgnoreRules {
+    pub fn parse(content: &str) -> Self {
+        let mut rules = Self::default();
+        content.lines().for_each(|line| rules.add(line));
+        rules
+    }
+
+    /// Reads the `.gbtignore` of `dir`, falling back to the defaults
+    pub fn load(dir: &Path) -> Self {
+        match fs::read_to_string(dir.join(".gbtignore")) {
+            Ok(content) => Self::parse(&content),
+            Err(_) => Self::parse(DEFAULT_GBTIGNORE),
+        }
+    }
+
+    pub fn add(&mut self, line: &str) {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        match Pattern::new(line.trim_start_matches('/')) {
+            Ok(pattern) => self.rules.push(Rule {
+                pattern,
+                negated,
+                dir_only,
+                anchored,
+            }),
+            Err(err) => warn!("Skipping .gbtignore Pattern {:}: {:}", line, err),
+        }
+    }
+
+    /// Whether `path`, relative to the ignore file, is ignored
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let relative = path.to_string_lossy().replace('\\', "/");
+        let name = path
+            .file_name()
+            .map(|f| f.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+        self.rules
+            .iter()
+            .rev()
+            .filter(|f| is_dir || !f.dir_only)
+            .find(|f| match f.anchored {
+                true => f.pattern.matches_with(&relative, options),
+                false => f.pattern.matches_with(&name, options),
+            })
+            .is_some_and(|f| !f.negated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_is_ignored() {
+        let mut rules =
+            IgnoreRules::parse(&format!("{:}*.blend1\n/Notes.txt\n", DEFAULT_GBTIGNORE));
+        assert!(rules.is_ignored(Path::new(".git"), true));
+        assert!(rules.is_ignored(Path::new("Textures/Cache"), true));
+        assert!(!rules.is_ignored(Path::new("Textures/Model"), true));
+        assert!(!rules.is_ignored(Path::new("Output"), false));
+        assert!(rules.is_ignored(Path::new("Source/Model/Furina.blend1"), false));
+        assert!(rules.is_ignored(Path::new("Notes.txt"), false));
+        assert!(!rules.is_ignored(Path::new("Source/Notes.txt"), false));
+
+        rules.add("!Output/");
+        assert!(!rules.is_ignored(Path::new("Output"), true));
+    }
+}
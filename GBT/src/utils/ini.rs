@@ -1,27 +1,240 @@
 use std::{
-    collections::HashMap,
-    fs::{self, OpenOptions},
-    io::Write,
-    path::PathBuf,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
     sync::Arc,
 };
 
 use anyhow::Result;
+use thiserror::Error;
 
 use crate::VERSION;
 
 use super::version::Version;
 
+/// Kinds of sections the builder can emit, in the order they are exported
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SectionKind {
+    /// `[Constants]`, run once on load. Every segment is merged into the one section
+    Constants,
+    /// `[Present]`, run every frame. Every segment is merged into the one section
+    Present,
+    /// `[Key…]` bindings
+    Key,
+    /// `[TextureOverride…]` and `[ShaderOverride…]`
+    Override,
+    /// `[CommandList…]` and `[CustomShader…]`
+    CommandList,
+    /// `[Resource…]`
+    Resource,
+}
+
+impl SectionKind {
+    fn title(&self) -> &str {
+        match self {
+            SectionKind::Constants => "Constants",
+            SectionKind::Present => "Present",
+            SectionKind::Key => "Keys",
+            SectionKind::Override => "Overrides",
+            SectionKind::CommandList => "Command Lists",
+            SectionKind::Resource => "Resources",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    And,
+    Or,
+}
+
+impl Display for Op {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let op = match self {
+            Op::Add => "+",
+            Op::Sub => "-",
+            Op::Mul => "*",
+            Op::Div => "/",
+            Op::Mod => "%",
+            Op::Equal => "==",
+            Op::NotEqual => "!=",
+            Op::Less => "<",
+            Op::LessEqual => "<=",
+            Op::Greater => ">",
+            Op::GreaterEqual => ">=",
+            Op::And => "&&",
+            Op::Or => "||",
+        };
+        write!(f, "{:}", op)
+    }
+}
+
+/// Expressions 3DMigoto evaluates in conditions and assignments
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    /// `$name`
+    Var(Box<str>),
+    /// Numbers, and builtins like `time` or `cursor_x`
+    Value(Box<str>),
+    Not(Box<Expr>),
+    Binary(Box<Expr>, Op, Box<Expr>),
+}
+
+impl Expr {
+    pub fn var(name: &str) -> Self {
+        Self::Var(name.trim_start_matches('$').into())
+    }
+
+    pub fn value(value: impl Display) -> Self {
+        Self::Value(value.to_string().into())
+    }
+
+    pub fn binary(self, op: Op, rhs: Expr) -> Self {
+        Self::Binary(Box::new(self), op, Box::new(rhs))
+    }
+
+    pub fn equals(self, rhs: Expr) -> Self {
+        self.binary(Op::Equal, rhs)
+    }
+
+    pub fn and(self, rhs: Expr) -> Self {
+        self.binary(Op::And, rhs)
+    }
+
+    pub fn or(self, rhs: Expr) -> Self {
+        self.binary(Op::Or, rhs)
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let operand = |expr: &Expr| match expr {
+            Expr::Binary(..) => format!("({:})", expr),
+            _ => expr.to_string(),
+        };
+        match self {
+            Expr::Var(name) => write!(f, "${:}", name),
+            Expr::Value(value) => write!(f, "{:}", value),
+            Expr::Not(expr) => write!(f, "!{:}", operand(expr)),
+            Expr::Binary(lhs, op, rhs) => write!(f, "{:} {:} {:}", operand(lhs), op, operand(rhs)),
+        }
+    }
+}
+
+/// A single line, or `if` block, of a section's command list
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    /// `key=value`
+    Field(Box<str>, Box<str>),
+    /// `global [persist] $name = value`, only valid in `[Constants]`
+    Global {
+        persist: bool,
+        name: Box<str>,
+        value: Expr,
+    },
+    /// `$name = expr`
+    Set(Box<str>, Expr),
+    /// `run = CommandList…`
+    Run(Box<str>),
+    /// `pre …`, run before the draw call
+    Pre(Box<Statement>),
+    /// `post …`, run after the draw call
+    Post(Box<Statement>),
+    /// Command list lines without a value, e.g. `clear`
+    Command(Box<str>),
+    /// `if`/`else`/`endif`. Empty `otherwise` branches are left out
+    If {
+        condition: Expr,
+        then: Vec<Statement>,
+        otherwise: Vec<Statement>,
+    },
+}
+
+impl Statement {
+    fn write(&self, f: &mut std::fmt::Formatter<'_>, depth: usize) -> std::fmt::Result {
+        match self {
+            Statement::If { .. } => {
+                self.write_branch(f, depth, "if")?;
+                writeln!(f, "{:}endif", "    ".repeat(depth))
+            }
+            _ => writeln!(f, "{:}{:}", "    ".repeat(depth), self.line()),
+        }
+    }
+
+    /// Writes a conditional, chaining an `else` holding a lone `if` as `else if`
+    fn write_branch(&self, f: &mut std::fmt::Formatter<'_>, depth: usize, keyword: &str) -> std::fmt::Result {
+        let Statement::If {
+            condition,
+            then,
+            otherwise,
+        } = self
+        else {
+            return self.write(f, depth);
+        };
+        let indent = "    ".repeat(depth);
+        writeln!(f, "{:}{:} {:}", indent, keyword, condition)?;
+        for statement in then {
+            statement.write(f, depth + 1)?;
+        }
+        match otherwise.as_slice() {
+            [] => Ok(()),
+            [chained @ Statement::If { .. }] => chained.write_branch(f, depth, "else if"),
+            _ => {
+                writeln!(f, "{:}else", indent)?;
+                for statement in otherwise {
+                    statement.write(f, depth + 1)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn line(&self) -> String {
+        match self {
+            Statement::Field(key, value) => format!("{:}={:}", key, value),
+            Statement::Global {
+                persist,
+                name,
+                value,
+            } => format!(
+                "global {:}${:} = {:}",
+                if *persist { "persist " } else { "" },
+                name,
+                value
+            ),
+            Statement::Set(name, value) => format!("${:} = {:}", name.trim_start_matches('$'), value),
+            Statement::Run(command_list) => format!("run = {:}", command_list),
+            Statement::Command(command) => command.to_string(),
+            Statement::Pre(statement) => format!("pre {:}", statement.line()),
+            Statement::Post(statement) => format!("post {:}", statement.line()),
+            // Conditionals cannot be prefixed, so they are only written through `write`
+            Statement::If { .. } => String::new(),
+        }
+    }
+}
+
 pub struct Ini {
     name: Box<str>,
     authors: Box<str>,
-    overrides: Vec<IniSegment>,
-    resources: Vec<IniSegment>,
+    sections: Vec<(SectionKind, IniSegment)>,
 }
 
 pub struct IniSegment {
     header: Arc<str>,
-    fields: HashMap<Box<str>, Box<str>>,
+    // Kept in insertion order, as override fields are run as a command list
+    statements: Vec<Statement>,
 }
 
 impl Ini {
@@ -30,50 +243,81 @@ impl Ini {
         Self {
             name: name.into(),
             authors: authors.as_str().into(),
-            overrides: vec![],
-            resources: vec![],
+            sections: vec![],
         }
     }
-    pub fn add_override(mut self, seg: IniSegment) -> Self {
-        self.overrides.push(seg);
+    pub fn add_section(mut self, kind: SectionKind, seg: IniSegment) -> Self {
+        self.sections.push((kind, seg));
         self
     }
-    pub fn add_resource(mut self, seg: IniSegment) -> Self {
-        self.resources.push(seg);
-        self
+    pub fn add_override(self, seg: IniSegment) -> Self {
+        self.add_section(SectionKind::Override, seg)
+    }
+    pub fn add_resource(self, seg: IniSegment) -> Self {
+        self.add_section(SectionKind::Resource, seg)
+    }
+    pub fn add_constant(self, statement: Statement) -> Self {
+        self.add_section(SectionKind::Constants, IniSegment::new("Constants").add_statement(statement))
+    }
+    pub fn add_present(self, statement: Statement) -> Self {
+        self.add_section(SectionKind::Present, IniSegment::new("Present").add_statement(statement))
+    }
+    pub fn segments_mut(&mut self) -> impl Iterator<Item = &mut IniSegment> {
+        self.sections.iter_mut().map(|f| &mut f.1)
     }
     pub fn export(&self, file_path: &PathBuf) -> Result<()> {
-        fs::create_dir_all(&file_path.parent().unwrap())?;
-        let mut file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(&file_path)?;
-        writeln!(file, "; Mod Name: {:}", self.name)?;
-        writeln!(file, "; Authors {:}", self.authors)?;
-
-        writeln!(file, "; Overrides---------------------------------",)?;
-        for ini_override in &self.overrides {
-            writeln!(file, "[{:}]", ini_override.header)?;
-            for field in &ini_override.fields {
-                writeln!(file, "{:}={:}", field.0, field.1)?;
-            }
-            writeln!(file, "")?;
+        fs::create_dir_all(file_path.parent().unwrap())?;
+        let content = self.to_string();
+        // Leave unchanged inis untouched so the watcher does not pick them up
+        if fs::read_to_string(file_path).is_ok_and(|f| f == content) {
+            return Ok(());
         }
+        fs::write(file_path, content)?;
+        Ok(())
+    }
+}
+
+impl Display for Ini {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "; Mod Name: {:}", self.name)?;
+        writeln!(f, "; Authors {:}", self.authors)?;
 
-        writeln!(file, "; Resources---------------------------------",)?;
-        for ini_resources in &self.resources {
-            writeln!(file, "[{:}]", ini_resources.header)?;
-            for field in &ini_resources.fields {
-                writeln!(file, "{:}={:}", field.0, field.1)?;
+        let kinds = [
+            SectionKind::Constants,
+            SectionKind::Present,
+            SectionKind::Key,
+            SectionKind::Override,
+            SectionKind::CommandList,
+            SectionKind::Resource,
+        ];
+        for kind in kinds {
+            let mut segments = self.sections.iter().filter(|f| f.0 == kind).map(|f| &f.1).peekable();
+            // Overrides and Resources are always titled, to keep older inis unchanged
+            if segments.peek().is_none() && !matches!(kind, SectionKind::Override | SectionKind::Resource) {
+                continue;
+            }
+            writeln!(f, "; {:-<42}", kind.title())?;
+            match kind {
+                SectionKind::Constants | SectionKind::Present => {
+                    writeln!(f, "[{:}]", kind.title())?;
+                    for statement in segments.flat_map(|f| &f.statements) {
+                        statement.write(f, 0)?;
+                    }
+                    writeln!(f)?;
+                }
+                _ => {
+                    for seg in segments {
+                        writeln!(f, "{:}", seg)?;
+                    }
+                }
             }
-            writeln!(file, "")?;
         }
         writeln!(
-            file,
+            f,
             "; .ini generated by GBT (GIMI Build Tool) Version:{}",
             Version::new(VERSION.version[0], VERSION.version[1], VERSION.version[2])
         )?;
-        writeln!(file, "; If you have any issues or find any bugs, please open a issue at https://github.com/Flamindemigod/AGMG-Tools/issues or contact @Flamindemigod on discord")?;
+        writeln!(f, "; If you have any issues or find any bugs, please open a issue at https://github.com/Flamindemigod/AGMG-Tools/issues or contact @Flamindemigod on discord")?;
         Ok(())
     }
 }
@@ -82,12 +326,568 @@ impl IniSegment {
     pub fn new(header: &str) -> Self {
         Self {
             header: header.into(),
-            fields: HashMap::new(),
+            statements: vec![],
         }
     }
 
-    pub fn add_field(mut self, key: &str, value: &str) -> Self {
-        self.fields.insert(key.into(), value.into());
+    pub fn add_field(self, key: &str, value: &str) -> Self {
+        self.add_statement(Statement::Field(key.into(), value.into()))
+    }
+
+    pub fn add_statement(mut self, statement: Statement) -> Self {
+        self.statements.push(statement);
         self
     }
+
+    pub fn header(&self) -> &str {
+        &self.header
+    }
+
+    pub fn statements(&self) -> &[Statement] {
+        &self.statements
+    }
+
+    pub fn statements_mut(&mut self) -> &mut Vec<Statement> {
+        &mut self.statements
+    }
+}
+
+impl Display for IniSegment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "[{:}]", self.header)?;
+        for statement in &self.statements {
+            statement.write(f, 0)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum IniError {
+    /// Failed to read or write the ini
+    ///
+    /// `(path, error message)`
+    #[error("{0:?}: {1}")]
+    Io(PathBuf, String),
+
+    /// Malformed line
+    ///
+    /// `(line number, error message)`
+    #[error("Line {0}: {1}")]
+    Parse(usize, String),
+}
+
+/// Meaning of a single line in the 3DMigoto ini dialect
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IniLine {
+    Blank,
+    /// `; comment`, kept with its `;`
+    Comment(String),
+    /// `[Section]`
+    Section(String),
+    /// `key = value`. Keys may repeat within a section
+    Field { key: String, value: String },
+    /// `if condition`
+    If(String),
+    /// `elif condition` or `else if condition`
+    ElseIf(String),
+    Else,
+    EndIf,
+    /// Command list lines without a value, e.g. `clear`
+    Command(String),
+}
+
+impl IniLine {
+    fn parse(line: &str) -> Self {
+        let trimmed = line.trim();
+        let lower = trimmed.to_lowercase();
+        let condition = |prefix: &str| trimmed[prefix.len()..].trim().to_owned();
+        if trimmed.is_empty() {
+            Self::Blank
+        } else if trimmed.starts_with(';') {
+            Self::Comment(trimmed.to_owned())
+        } else if trimmed.starts_with('[') && trimmed.ends_with(']') {
+            Self::Section(trimmed[1..trimmed.len() - 1].trim().to_owned())
+        } else if lower.starts_with("if ") {
+            Self::If(condition("if "))
+        } else if lower.starts_with("elif ") {
+            Self::ElseIf(condition("elif "))
+        } else if lower.starts_with("else if ") {
+            Self::ElseIf(condition("else if "))
+        } else if lower == "else" {
+            Self::Else
+        } else if lower == "endif" {
+            Self::EndIf
+        } else if let Some((key, value)) = trimmed.split_once('=') {
+            Self::Field {
+                key: key.trim().to_owned(),
+                value: value.trim().to_owned(),
+            }
+        } else {
+            Self::Command(trimmed.to_owned())
+        }
+    }
+}
+
+impl Display for IniLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IniLine::Blank => Ok(()),
+            IniLine::Comment(comment) => write!(f, "{:}", comment),
+            IniLine::Section(header) => write!(f, "[{:}]", header),
+            IniLine::Field { key, value } => write!(f, "{:}={:}", key, value),
+            IniLine::If(condition) => write!(f, "if {:}", condition),
+            IniLine::ElseIf(condition) => write!(f, "elif {:}", condition),
+            IniLine::Else => write!(f, "else"),
+            IniLine::EndIf => write!(f, "endif"),
+            IniLine::Command(command) => write!(f, "{:}", command),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DocumentLine {
+    /// Original text including its line ending. `None` once the line is edited
+    raw: Option<String>,
+    line: IniLine,
+}
+
+/// Field of a section, with the 1-based line it was found at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IniField<'a> {
+    pub key: &'a str,
+    pub value: &'a str,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IniSection<'a> {
+    pub name: &'a str,
+    pub line: usize,
+    pub fields: Vec<IniField<'a>>,
+}
+
+impl<'a> IniSection<'a> {
+    /// First value of `key`, which 3DMigoto matches case-insensitively
+    pub fn get(&self, key: &str) -> Option<&'a str> {
+        self.fields
+            .iter()
+            .find(|f| f.key.eq_ignore_ascii_case(key))
+            .map(|f| f.value)
+    }
+}
+
+/// An existing ini, parsed so that it can be edited without losing comments,
+/// ordering or conditionals. Untouched lines are written back byte-for-byte
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IniDocument {
+    lines: Vec<DocumentLine>,
+    newline: &'static str,
+}
+
+impl FromStr for IniDocument {
+    type Err = IniError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let newline = match s.contains("\r\n") {
+            true => "\r\n",
+            false => "\n",
+        };
+        let mut lines = vec![];
+        let mut depth = 0usize;
+        for (index, raw) in s.split_inclusive('\n').enumerate() {
+            let line = IniLine::parse(raw.trim_start_matches('\u{feff}'));
+            match &line {
+                IniLine::If(_) => depth += 1,
+                IniLine::ElseIf(_) | IniLine::Else if depth == 0 => {
+                    return Err(IniError::Parse(index + 1, "else without a matching if".to_owned()))
+                }
+                IniLine::EndIf if depth == 0 => {
+                    return Err(IniError::Parse(index + 1, "endif without a matching if".to_owned()))
+                }
+                IniLine::EndIf => depth -= 1,
+                IniLine::Section(_) if depth != 0 => {
+                    return Err(IniError::Parse(index + 1, "Section started inside an if block".to_owned()))
+                }
+                _ => (),
+            }
+            lines.push(DocumentLine {
+                raw: Some(raw.to_owned()),
+                line,
+            });
+        }
+        if depth != 0 {
+            return Err(IniError::Parse(lines.len(), "if without a matching endif".to_owned()));
+        }
+        Ok(Self { lines, newline })
+    }
+}
+
+impl Display for IniDocument {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for line in &self.lines {
+            match &line.raw {
+                Some(raw) => write!(f, "{:}", raw)?,
+                None => write!(f, "{:}{:}", line.line, self.newline)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl IniDocument {
+    pub fn read(path: &Path) -> Result<Self, IniError> {
+        let content =
+            fs::read_to_string(path).map_err(|e| IniError::Io(path.to_path_buf(), e.to_string()))?;
+        Self::from_str(&content)
+    }
+
+    pub fn write(&self, path: &Path) -> Result<(), IniError> {
+        fs::write(path, self.to_string()).map_err(|e| IniError::Io(path.to_path_buf(), e.to_string()))
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = &IniLine> {
+        self.lines.iter().map(|f| &f.line)
+    }
+
+    /// Every section in file order. Fields inside `if` blocks belong to their section
+    pub fn sections(&self) -> Vec<IniSection<'_>> {
+        let mut sections: Vec<IniSection> = vec![];
+        for (index, line) in self.lines.iter().enumerate() {
+            match (&line.line, sections.last_mut()) {
+                (IniLine::Section(name), _) => sections.push(IniSection {
+                    name,
+                    line: index + 1,
+                    fields: vec![],
+                }),
+                (IniLine::Field { key, value }, Some(section)) => section.fields.push(IniField {
+                    key,
+                    value,
+                    line: index + 1,
+                }),
+                _ => (),
+            }
+        }
+        sections
+    }
+
+    /// Every section as a builder segment, nesting conditionals into
+    /// [`Statement::If`]. Comments are dropped
+    pub fn segments(&self) -> Vec<IniSegment> {
+        struct Frame {
+            condition: Expr,
+            then: Vec<Statement>,
+            otherwise: Vec<Statement>,
+            in_else: bool,
+            /// Opened by an `else if`, so closed by its parent's `endif`
+            chained: bool,
+        }
+        fn push(stack: &mut [Frame], seg: &mut IniSegment, statement: Statement) {
+            match stack.last_mut() {
+                Some(frame) if frame.in_else => frame.otherwise.push(statement),
+                Some(frame) => frame.then.push(statement),
+                None => seg.statements.push(statement),
+            }
+        }
+
+        let mut segments: Vec<IniSegment> = vec![];
+        let mut stack: Vec<Frame> = vec![];
+        for line in self.lines() {
+            if let IniLine::Section(header) = line {
+                segments.push(IniSegment::new(header));
+                continue;
+            }
+            let Some(seg) = segments.last_mut() else {
+                continue;
+            };
+            let frame = |condition: &str, chained: bool| Frame {
+                condition: Expr::Value(condition.into()),
+                then: vec![],
+                otherwise: vec![],
+                in_else: false,
+                chained,
+            };
+            match line {
+                IniLine::Field { key, value } => {
+                    push(&mut stack, seg, Statement::Field(key.as_str().into(), value.as_str().into()))
+                }
+                IniLine::Command(command) => push(&mut stack, seg, Statement::Command(command.as_str().into())),
+                IniLine::If(condition) => stack.push(frame(condition, false)),
+                IniLine::ElseIf(condition) => {
+                    if let Some(parent) = stack.last_mut() {
+                        parent.in_else = true;
+                    }
+                    stack.push(frame(condition, true));
+                }
+                IniLine::Else => {
+                    if let Some(parent) = stack.last_mut() {
+                        parent.in_else = true;
+                    }
+                }
+                IniLine::EndIf => {
+                    while let Some(closed) = stack.pop() {
+                        let chained = closed.chained;
+                        let statement = Statement::If {
+                            condition: closed.condition,
+                            then: closed.then,
+                            otherwise: closed.otherwise,
+                        };
+                        push(&mut stack, seg, statement);
+                        if !chained {
+                            break;
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+        segments
+    }
+
+    pub fn get(&self, section: &str, key: &str) -> Option<&str> {
+        self.sections()
+            .into_iter()
+            .find(|f| f.name.eq_ignore_ascii_case(section))
+            .and_then(|f| f.get(key))
+    }
+
+    /// Range of lines from a section's header up to its last non blank line
+    fn section_range(&self, section: &str) -> Option<(usize, usize)> {
+        let start = self
+            .lines
+            .iter()
+            .position(|f| matches!(&f.line, IniLine::Section(name) if name.eq_ignore_ascii_case(section)))?;
+        let next = self.lines[start + 1..]
+            .iter()
+            .position(|f| matches!(f.line, IniLine::Section(_)))
+            .map_or(self.lines.len(), |f| start + 1 + f);
+        let end = (start..next)
+            .rev()
+            .find(|f| !matches!(self.lines[*f].line, IniLine::Blank | IniLine::Comment(_)))
+            .unwrap_or(start);
+        Some((start, end))
+    }
+
+    /// Replaces the value of the first `key` in `section`, keeping the spacing
+    /// around it. Returns whether the field was found
+    pub fn set(&mut self, section: &str, key: &str, value: &str) -> bool {
+        let Some((start, end)) = self.section_range(section) else {
+            return false;
+        };
+        let Some(line) = self.lines[start..=end].iter_mut().find(
+            |f| matches!(&f.line, IniLine::Field { key: k, .. } if k.eq_ignore_ascii_case(key)),
+        ) else {
+            return false;
+        };
+        if let (Some(raw), IniLine::Field { value: old, .. }) = (&line.raw, &line.line) {
+            let (head, tail) = raw.split_once('=').unwrap();
+            let padding = &tail[..tail.len() - tail.trim_start().len()];
+            let ending = &tail[padding.len() + old.len()..];
+            line.raw = Some(format!("{:}={:}{:}{:}", head, padding, value, ending));
+        }
+        if let IniLine::Field { value: old, .. } = &mut line.line {
+            *old = value.to_owned();
+        }
+        true
+    }
+
+    fn insert(&mut self, index: usize, line: IniLine) {
+        // A last line without a line ending needs one before anything follows it
+        if let Some(raw) = index
+            .checked_sub(1)
+            .and_then(|f| self.lines.get_mut(f))
+            .and_then(|f| f.raw.as_mut())
+        {
+            if !raw.ends_with('\n') {
+                raw.push_str(self.newline);
+            }
+        }
+        self.lines.insert(index, DocumentLine { raw: None, line });
+    }
+
+    /// Appends a field to the end of `section`, adding the section if it is missing
+    pub fn add_field(&mut self, section: &str, key: &str, value: &str) {
+        let field = IniLine::Field {
+            key: key.to_owned(),
+            value: value.to_owned(),
+        };
+        match self.section_range(section) {
+            Some((_, end)) => self.insert(end + 1, field),
+            None => {
+                self.add_section(&IniSegment::new(section));
+                self.insert(self.lines.len() - 1, field);
+            }
+        }
+    }
+
+    /// Appends a section built with [`IniSegment`] to the end of the document
+    pub fn add_section(&mut self, seg: &IniSegment) {
+        if self.lines.last().is_some_and(|f| f.line != IniLine::Blank) {
+            self.insert(self.lines.len(), IniLine::Blank);
+        }
+        for line in seg.to_string().lines() {
+            self.insert(self.lines.len(), IniLine::parse(line));
+            // Keep the indentation of conditionals
+            self.lines.last_mut().unwrap().raw = Some(format!("{:}{:}", line, self.newline));
+        }
+        self.insert(self.lines.len(), IniLine::Blank);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INI: &str = "; Hand tuned\r
+[Constants]\r
+global persist $active = 0\r
+\r
+[TextureOverrideBody]\r
+hash = 1a2b3c4d\r
+  run = CommandListA\r
+  run = CommandListB\r
+if $active == 1\r
+    ps-t0 = ResourceBodyA\r
+else if $active == 2\r
+    ps-t0 = ResourceBodyB\r
+else\r
+    clear\r
+endif\r
+\r
+[ResourceBodyA]\r
+filename=BodyA.dds";
+
+    #[test]
+    pub fn test_round_trip() {
+        let ini = IniDocument::from_str(INI).unwrap();
+        assert_eq!(ini.to_string(), INI);
+        let sections = ini.sections();
+        assert_eq!(sections.len(), 3);
+        assert_eq!(sections[1].fields.len(), 5);
+        assert_eq!(sections[1].fields[2].line, 8);
+        assert_eq!(ini.get("textureoverridebody", "ps-t0"), Some("ResourceBodyA"));
+        assert!(ini.lines().any(|f| f == &IniLine::Command("clear".to_owned())));
+
+        let segments = ini.segments();
+        assert_eq!(segments[1].statements().len(), 4);
+        assert_eq!(
+            segments[1].to_string(),
+            "[TextureOverrideBody]
+hash=1a2b3c4d
+run=CommandListA
+run=CommandListB
+if $active == 1
+    ps-t0=ResourceBodyA
+else if $active == 2
+    ps-t0=ResourceBodyB
+else
+    clear
+endif
+"
+        );
+    }
+
+    #[test]
+    pub fn test_edit() {
+        let mut ini = IniDocument::from_str(INI).unwrap();
+        assert!(ini.set("TextureOverrideBody", "hash", "deadbeef"));
+        assert!(!ini.set("TextureOverrideBody", "vb0", "ResourceBodyVB"));
+        ini.add_field("TextureOverrideBody", "handling", "skip");
+        ini.add_field("ResourceBodyA", "type", "Texture2D");
+        ini.add_section(&IniSegment::new("ResourceBodyB").add_field("filename", "BodyB.dds"));
+        let edited = ini.to_string();
+        assert!(edited.starts_with("; Hand tuned\r\n[Constants]"));
+        assert!(edited.contains("hash = deadbeef\r\n"));
+        assert!(edited.contains("endif\r\nhandling=skip\r\n\r\n[ResourceBodyA]"));
+        assert!(edited.ends_with("filename=BodyA.dds\r\ntype=Texture2D\r\n\r\n[ResourceBodyB]\r\nfilename=BodyB.dds\r\n\r\n"));
+        assert_eq!(IniDocument::from_str(&edited).unwrap().to_string(), edited);
+    }
+
+    #[test]
+    pub fn test_unbalanced() {
+        assert_eq!(
+            IniDocument::from_str("[A]\nif $a\n[B]\n"),
+            Err(IniError::Parse(3, "Section started inside an if block".to_owned()))
+        );
+        assert!(IniDocument::from_str("[A]\nendif\n").is_err());
+        assert!(IniDocument::from_str("[A]\nif $a\n").is_err());
+    }
+
+    #[test]
+    pub fn test_export_overwrites() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("Mod.ini");
+        let long = Ini::new("Mod", "A, B".to_owned())
+            .add_override(IniSegment::new("TextureOverrideA").add_field("hash", "1a2b3c4d").add_field("this", "ResourceA"))
+            .add_resource(IniSegment::new("ResourceA").add_field("filename", "A.dds"));
+        long.export(&path).unwrap();
+        let short = Ini::new("Mod", "A, B".to_owned());
+        short.export(&path).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), short.to_string());
+    }
+
+    #[test]
+    pub fn test_statements() {
+        let active = Expr::var("active");
+        let ini = Ini::new("Mod", "A".to_owned())
+            .add_resource(IniSegment::new("ResourceA").add_field("filename", "A.dds"))
+            .add_section(
+                SectionKind::CommandList,
+                IniSegment::new("CommandListA").add_statement(Statement::If {
+                    condition: active.clone().equals(Expr::value(1)).and(Expr::var("$shown")),
+                    then: vec![Statement::Field("ps-t0".into(), "ResourceA".into())],
+                    otherwise: vec![Statement::Pre(Box::new(Statement::Run("CommandListB".into())))],
+                }),
+            )
+            .add_constant(Statement::Global {
+                persist: true,
+                name: "active".into(),
+                value: Expr::value(0),
+            })
+            .add_present(Statement::Post(Box::new(Statement::Set(
+                "active".into(),
+                active.binary(Op::Add, Expr::value(1)).binary(Op::Mod, Expr::value(2)),
+            ))))
+            .add_constant(Statement::Global {
+                persist: false,
+                name: "shown".into(),
+                value: Expr::value(1),
+            });
+        let content = ini.to_string();
+        let body = content.lines().skip(2).take_while(|f| !f.starts_with("; .ini")).collect::<Vec<_>>();
+        assert_eq!(
+            body.join("\n"),
+            "; Constants---------------------------------
+[Constants]
+global persist $active = 0
+global $shown = 1
+
+; Present-----------------------------------
+[Present]
+post $active = ($active + 1) % 2
+
+; Overrides---------------------------------
+; Command Lists-----------------------------
+[CommandListA]
+if ($active == 1) && $shown
+    ps-t0=ResourceA
+else
+    pre run = CommandListB
+endif
+
+; Resources---------------------------------
+[ResourceA]
+filename=A.dds
+"
+        );
+        let mut doc = IniDocument::from_str("[ResourceA]\nfilename=A.dds\n").unwrap();
+        doc.add_section(&IniSegment::new("CommandListB").add_statement(Statement::If {
+            condition: Expr::var("active"),
+            then: vec![Statement::Run("CommandListA".into())],
+            otherwise: vec![],
+        }));
+        assert_eq!(
+            doc.to_string(),
+            "[ResourceA]\nfilename=A.dds\n\n[CommandListB]\nif $active\n    run = CommandListA\nendif\n\n"
+        );
+    }
 }
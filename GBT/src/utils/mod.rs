@@ -5,5 +5,6 @@ pub mod download;
 pub mod interactions;
 pub mod copy;
 pub mod exec_validation;
+pub mod ignore;
 pub mod ini;
 pub mod version;
\ No newline at end of file